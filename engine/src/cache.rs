@@ -0,0 +1,217 @@
+use crate::error::Error;
+use std::env;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process;
+use wasmtime::{Engine, Module};
+
+/// Directory of compiled modules, so unchanged modules don't have to be
+/// compiled again on every launch.  Entries are keyed on the module's bytes
+/// and on the engine's compilation settings, so a different wasmtime version
+/// or config just misses the cache.  Each source path keeps only its latest
+/// entry, so rebuilding a module doesn't grow the cache.
+pub struct ModuleCache {
+    dir: PathBuf,
+}
+
+impl ModuleCache {
+    pub fn new<T: Into<PathBuf>>(dir: T) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Load the compiled module from the cache, or compile it and save the
+    /// result in place of any entry for an older build loaded from `source`.
+    /// Problems with the cache itself are reported and otherwise ignored.
+    pub fn load(
+        &self,
+        engine: &Engine,
+        source: &Path,
+        bytes: &[u8],
+    ) -> Result<Module, Error> {
+        let prefix = source_prefix(source);
+        let path = self
+            .dir
+            .join(format!("{prefix}-{}", cache_key(engine, bytes)));
+
+        if path.exists() {
+            // SAFETY: files in the cache directory are only ever written by
+            // `save` from `Module::serialize` output for this key.  The
+            // directory must only be writable by the user running the engine
+            // (see `default_dir`), and anyone who can write there can already
+            // run code as that user.
+            match unsafe { Module::deserialize_file(engine, &path) } {
+                Ok(module) => return Ok(module),
+                Err(e) => {
+                    eprintln!("Ignoring cached module {}: {e}", path.display())
+                }
+            }
+        }
+
+        let module = Module::new(engine, bytes).map_err(Error::Compile)?;
+
+        match save(&module, &path) {
+            Ok(()) => self.prune(&prefix, &path),
+            Err(e) => {
+                eprintln!("Failed to cache module at {}: {e}", path.display())
+            }
+        }
+
+        Ok(module)
+    }
+
+    /// Remove the entries for `prefix`'s source other than `keep`
+    fn prune(&self, prefix: &str, keep: &Path) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Failed to prune {}: {e}", self.dir.display());
+                return;
+            }
+        };
+
+        let prefix = format!("{prefix}-");
+
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let stale = path != keep
+                && path.extension().is_some_and(|ext| ext == "cwasm")
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(&prefix));
+
+            if stale && let Err(e) = fs::remove_file(&path) {
+                eprintln!("Failed to prune {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+/// Per-user cache directory: `$XDG_CACHE_HOME/pipe-cleaner`, or else
+/// `$HOME/.cache/pipe-cleaner`.  `None` if neither is set to an absolute
+/// path, since a directory relative to wherever the engine was launched from
+/// could belong to anyone.
+pub fn default_dir() -> Option<PathBuf> {
+    user_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// `$<var>/pipe-cleaner`, or else `$HOME/<under_home>/pipe-cleaner`, as the
+/// XDG base directory spec lays out.  Relative paths in either variable are
+/// ignored, as the spec says they should be.
+pub fn user_dir(var: &str, under_home: &str) -> Option<PathBuf> {
+    let absolute = |var| {
+        env::var_os(var)
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+    };
+
+    let base = absolute(var)
+        .or_else(|| absolute("HOME").map(|home| home.join(under_home)))?;
+
+    Some(base.join("pipe-cleaner"))
+}
+
+fn save(module: &Module, path: &Path) -> wasmtime::Result<()> {
+    let serialized = module.serialize()?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Write to a temporary file first so another launch never sees half an
+    // entry.  It's named after this process, so two launches compiling the
+    // same module don't write over each other's.
+    let tmp = path.with_extension(format!("{}.tmp", process::id()));
+    fs::write(&tmp, serialized)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Hash of the path a module was loaded from, which starts the names of its
+/// entries so older ones can be found and pruned
+fn source_prefix(source: &Path) -> String {
+    let source = fs::canonicalize(source).unwrap_or_else(|_| source.into());
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Rest of the file name for a module's cache entry: 128 bits of hash over the module
+/// and the engine's compatibility hash, to make collisions implausible
+fn cache_key(engine: &Engine, bytes: &[u8]) -> String {
+    let [a, b] = [0u8, 1].map(|salt| {
+        let mut hasher = DefaultHasher::new();
+        salt.hash(&mut hasher);
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+        bytes.hash(&mut hasher);
+        hasher.finish()
+    });
+
+    format!("{a:016x}{b:016x}.cwasm")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh cache directory and module path for one test
+    fn scratch(test: &str) -> (PathBuf, PathBuf) {
+        let dir = env::temp_dir()
+            .join(format!("pipe-cleaner-cache-{test}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        (dir.join("cache"), dir.join("game.wasm"))
+    }
+
+    fn entries(dir: &Path) -> Vec<PathBuf> {
+        let mut entries = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    fn module(global: u32) -> Vec<u8> {
+        let wat = format!("(module (global i32 (i32.const {global})))");
+        wat::parse_str(wat).unwrap()
+    }
+
+    #[test]
+    fn rebuilt_module_replaces_its_entry() {
+        let (dir, source) = scratch("rebuilt");
+        let engine = Engine::default();
+        let cache = ModuleCache::new(&dir);
+
+        cache.load(&engine, &source, &module(1)).unwrap();
+        let first = entries(&dir);
+        assert_eq!(first.len(), 1);
+
+        cache.load(&engine, &source, &module(2)).unwrap();
+        let second = entries(&dir);
+        assert_eq!(second.len(), 1);
+        assert_ne!(first, second);
+
+        let _ = fs::remove_dir_all(dir.parent().unwrap());
+    }
+
+    #[test]
+    fn other_sources_keep_their_entries() {
+        let (dir, source) = scratch("sources");
+        let other = source.with_file_name("other.wasm");
+        let engine = Engine::default();
+        let cache = ModuleCache::new(&dir);
+
+        cache.load(&engine, &source, &module(1)).unwrap();
+        cache.load(&engine, &other, &module(1)).unwrap();
+        cache.load(&engine, &other, &module(2)).unwrap();
+        assert_eq!(entries(&dir).len(), 2);
+
+        // Unchanged modules are still hits
+        let before = entries(&dir);
+        cache.load(&engine, &source, &module(1)).unwrap();
+        assert_eq!(entries(&dir), before);
+
+        let _ = fs::remove_dir_all(dir.parent().unwrap());
+    }
+}
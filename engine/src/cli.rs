@@ -0,0 +1,258 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const DEFAULT_MODULE: &str =
+    "target/wasm32-unknown-unknown/release/base_game.wasm";
+
+const DEFAULT_SIZE: (u32, u32) = (800, 600);
+const DEFAULT_FOV: f32 = 90.0;
+pub const DEFAULT_TICK_RATE: f64 = 120.0;
+pub const DEFAULT_RINGS: u32 = 20;
+const DEFAULT_SNAPSHOT: &str = "snapshot.pcs";
+
+pub const USAGE: &str = "\
+Usage: pipe-cleaner [OPTIONS] [MODULE...]
+
+Runs the given game modules, or the base game if none are given.
+
+Options:
+    --size WxH          Window size in pixels [default: 800x600]
+    --fullscreen        Start fullscreen
+    --fov DEGREES       Vertical field of view [default: 90]
+    --backend LIST      Graphics backends to try, comma-separated from
+                        vulkan, metal, dx12 and gl [default: $WGPU_BACKEND,
+                        or all]
+    --tick-rate HZ      Simulation ticks per second [default: 120]
+    --rings N           Number of pipe rings [default: 20]
+    --seed N            Seed for the random numbers given to guests
+                        [default: from the clock]
+    --headless          Run without a window or renderer
+    --ticks N           Stop after this many ticks
+    --snapshot FILE     Where F5 saves and F9 loads the guests' state
+                        [default: snapshot.pcs]
+    --restore           Load the snapshot file once modules are running
+    --list              Print each module's manifest and exit
+    --help              Print this message and exit";
+
+/// Settings given on the command line
+#[derive(Clone, Debug)]
+pub struct Cli {
+    pub modules: Vec<PathBuf>,
+    pub size: (u32, u32),
+    pub fullscreen: bool,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+    /// `None` leaves the choice to `WGPU_BACKEND`, or else tries them all
+    pub backends: Option<wgpu::Backends>,
+    pub tick_rate: f64,
+    pub rings: u32,
+    pub seed: Option<u64>,
+    pub headless: bool,
+    pub ticks: Option<u64>,
+    pub snapshot: PathBuf,
+    pub restore: bool,
+    pub list: bool,
+    pub help: bool,
+}
+
+impl Default for Cli {
+    fn default() -> Self {
+        Self {
+            modules: Vec::new(),
+            size: DEFAULT_SIZE,
+            fullscreen: false,
+            fov: DEFAULT_FOV,
+            backends: None,
+            tick_rate: DEFAULT_TICK_RATE,
+            rings: DEFAULT_RINGS,
+            seed: None,
+            headless: false,
+            ticks: None,
+            snapshot: PathBuf::from(DEFAULT_SNAPSHOT),
+            restore: false,
+            list: false,
+            help: false,
+        }
+    }
+}
+
+impl Cli {
+    /// Parse arguments, not including the program name
+    pub fn parse(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<Self, String> {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--size" => {
+                    let size = value::<String>(&arg, &mut args)?;
+
+                    cli.size = size
+                        .split_once('x')
+                        .and_then(|(w, h)| {
+                            Some((w.parse().ok()?, h.parse().ok()?))
+                        })
+                        .filter(|&(w, h)| w > 0 && h > 0)
+                        .ok_or_else(|| format!("Invalid size {size:?}"))?;
+                }
+                "--fullscreen" => cli.fullscreen = true,
+                "--fov" => {
+                    cli.fov = value(&arg, &mut args)?;
+
+                    if !(cli.fov > 0.0 && cli.fov < 180.0) {
+                        return Err(format!("Invalid FOV {}", cli.fov));
+                    }
+                }
+                "--backend" => {
+                    let list = value::<String>(&arg, &mut args)?;
+                    let backends = wgpu::Backends::from_comma_list(&list);
+
+                    if backends.is_empty() {
+                        return Err(format!("No known backends in {list:?}"));
+                    }
+
+                    cli.backends = Some(backends);
+                }
+                "--tick-rate" => {
+                    cli.tick_rate = value(&arg, &mut args)?;
+
+                    if !(cli.tick_rate.is_finite() && cli.tick_rate > 0.0) {
+                        return Err(format!(
+                            "Invalid tick rate {}",
+                            cli.tick_rate
+                        ));
+                    }
+                }
+                "--rings" => cli.rings = value(&arg, &mut args)?,
+                "--seed" => cli.seed = Some(value(&arg, &mut args)?),
+                "--headless" => cli.headless = true,
+                "--ticks" => cli.ticks = Some(value(&arg, &mut args)?),
+                "--snapshot" => cli.snapshot = value(&arg, &mut args)?,
+                "--restore" => cli.restore = true,
+                "--list" => cli.list = true,
+                "--help" | "-h" => cli.help = true,
+                flag if flag.starts_with('-') => {
+                    return Err(format!("Unknown option {flag}"));
+                }
+                _ => cli.modules.push(PathBuf::from(arg)),
+            }
+        }
+
+        if cli.modules.is_empty() {
+            cli.modules.push(PathBuf::from(DEFAULT_MODULE));
+        }
+
+        Ok(cli)
+    }
+
+    /// Seconds of game time covered by each tick
+    pub fn tick_duration(&self) -> f64 {
+        self.tick_rate.recip()
+    }
+}
+
+/// Parse the argument following `flag`
+fn value<T>(
+    flag: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = args.next().ok_or_else(|| format!("{flag} needs a value"))?;
+
+    value
+        .parse()
+        .map_err(|e| format!("Invalid value {value:?} for {flag}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults() {
+        let cli = parse(&[]).unwrap();
+
+        assert_eq!(cli.modules, [PathBuf::from(DEFAULT_MODULE)]);
+        assert_eq!(cli.size, DEFAULT_SIZE);
+        assert_eq!(cli.tick_rate, DEFAULT_TICK_RATE);
+        assert_eq!(cli.rings, DEFAULT_RINGS);
+        assert!(!cli.headless && !cli.restore && !cli.help);
+    }
+
+    #[test]
+    fn options_and_modules() {
+        let cli = parse(&[
+            "a.wasm",
+            "--size",
+            "1024x768",
+            "--fov",
+            "60",
+            "--tick-rate",
+            "60",
+            "--rings",
+            "5",
+            "--seed",
+            "42",
+            "--headless",
+            "--ticks",
+            "10",
+            "--snapshot",
+            "save.pcs",
+            "--restore",
+            "b.wasm",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.modules, [PathBuf::from("a.wasm"), "b.wasm".into()]);
+        assert_eq!(cli.size, (1024, 768));
+        assert_eq!(cli.fov, 60.0);
+        assert_eq!(cli.tick_duration(), 1.0 / 60.0);
+        assert_eq!(cli.rings, 5);
+        assert_eq!(cli.seed, Some(42));
+        assert!(cli.headless && cli.restore);
+        assert_eq!(cli.ticks, Some(10));
+        assert_eq!(cli.snapshot, PathBuf::from("save.pcs"));
+    }
+
+    #[test]
+    fn backends() {
+        let cli = parse(&["--backend", "vulkan,gl"]).unwrap();
+        assert_eq!(
+            cli.backends,
+            Some(wgpu::Backends::VULKAN | wgpu::Backends::GL)
+        );
+        assert!(parse(&["--backend", "nonsense"]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        for args in [
+            &["--size"][..],
+            &["--size", "800"],
+            &["--size", "0x600"],
+            &["--size", "axb"],
+            &["--fov", "0"],
+            &["--fov", "180"],
+            &["--fov", "NaN"],
+            &["--tick-rate", "0"],
+            &["--tick-rate", "inf"],
+            &["--tick-rate", "-1"],
+            &["--rings", "-1"],
+            &["--seed", "x"],
+            &["--ticks"],
+            &["--bogus"],
+        ] {
+            assert!(parse(args).is_err(), "{args:?}");
+        }
+    }
+}
@@ -0,0 +1,404 @@
+//! Game modules built as components against the `game` world in
+//! `wit/pipe-cleaner.wit`, as an alternative to the raw imports in `wasm`.
+//! Components get the same world and limits as raw modules, but only the
+//! part of the interface the WIT world covers: entities, logging, input,
+//! queries, random numbers, timers and collisions.
+//!
+//! This is deliberately a subset, not a second copy of the raw ABI.
+//! Components get no think functions, custom models, storage or messages,
+//! and have no manifest, so no capabilities and no ABI version check beyond
+//! the WIT types themselves.  Without the foreign entities capability they
+//! can only overwrite and remove their own entities.  They also can't be
+//! hot-reloaded or included in snapshots.  Games that need any of that use
+//! the raw imports.
+
+use crate::error::Error;
+use crate::wasm::{self, Config};
+use crate::wasm_entity::{self, Handle};
+use crate::world::{ModuleId, Timer, WasmWorld};
+use pipe_cleaner_shared::LogLevel;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use wasmtime::component::{Component, HasSelf, Linker};
+use wasmtime::{Engine, Store, StoreLimits, StoreLimitsBuilder, Trap};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "../wit",
+        world: "game",
+    });
+}
+
+use bindings::Game;
+use bindings::pipe_cleaner::engine::host;
+use bindings::pipe_cleaner::engine::types::{
+    EngineFields, Entity, EntityQuery, InputState, LogLevel as WitLogLevel,
+    PipePosition,
+};
+
+/// Layer field of the wasm header, which is 1 for components and 0 for core
+/// modules
+const COMPONENT_LAYER: [u8; 2] = [1, 0];
+
+/// Whether the file at `path` looks like a component rather than a core
+/// module
+pub fn is_component(path: &Path) -> bool {
+    let mut header = [0u8; 8];
+
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|()| header[6..] == COMPONENT_LAYER)
+}
+
+/// Per-component data available to host functions
+pub struct ComponentState {
+    name: String,
+    module: ModuleId,
+    world: Rc<RefCell<WasmWorld>>,
+    log_level: LogLevel,
+    limits: StoreLimits,
+    max_entities: usize,
+}
+
+/// Runs one component, in the same way `wasm::Host` runs a core module
+pub struct Host {
+    config: Config,
+    store: Store<ComponentState>,
+    component: Component,
+    linker: Linker<ComponentState>,
+    game: Option<Game>,
+    module_id: ModuleId,
+    world: Rc<RefCell<WasmWorld>>,
+}
+
+impl Host {
+    pub fn new<T: Into<PathBuf>>(
+        path: T,
+        world: Rc<RefCell<WasmWorld>>,
+        config: Config,
+    ) -> Result<Self, Error> {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.consume_fuel(config.fuel_per_frame.is_some());
+        let engine = Engine::new(&engine_config).map_err(Error::Engine)?;
+        let path = path.into();
+        let bytes = wasm::read_module(&path)?;
+        let component =
+            Component::new(&engine, bytes).map_err(Error::Compile)?;
+
+        let mut linker = Linker::new(&engine);
+
+        Game::add_to_linker::<_, HasSelf<_>>(&mut linker, |state| state)
+            .map_err(Error::Link)?;
+
+        let module_id = world.borrow_mut().register_module();
+
+        let state = ComponentState {
+            name: wasm::file_name(&path),
+            module: module_id,
+            world: Rc::clone(&world),
+            log_level: config.log_level,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(config.max_memory)
+                .table_elements(config.max_table_elements)
+                .build(),
+            max_entities: config.max_entities,
+        };
+
+        let mut store = Store::new(&engine, state);
+        store.limiter(|state| &mut state.limits);
+        wasm::refuel(&mut store, &config);
+
+        Ok(Self {
+            config,
+            store,
+            component,
+            linker,
+            game: None,
+            module_id,
+            world,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.store.data().name
+    }
+
+    pub fn module_id(&self) -> ModuleId {
+        self.module_id
+    }
+
+    /// Give the component a fresh fuel budget for the coming frame
+    pub fn refuel(&mut self) {
+        wasm::refuel(&mut self.store, &self.config);
+    }
+
+    /// Instantiate the component and call its `init` export
+    pub fn run(&mut self) -> Result<(), Error> {
+        let game =
+            Game::instantiate(&mut self.store, &self.component, &self.linker)
+                .map_err(Error::Link)?;
+
+        game.call_init(&mut self.store).map_err(guest_error)?;
+        self.game = Some(game);
+        Ok(())
+    }
+
+    /// Call the component's `update` export with the time step in seconds
+    pub fn update(&mut self, dt: f32) -> Result<(), Error> {
+        let Some(game) = &self.game else {
+            return Ok(());
+        };
+
+        game.call_update(&mut self.store, dt).map_err(guest_error)
+    }
+
+    /// Call the component's `on-collision` export for each collision
+    /// involving an entity it owns, with its own entity first
+    pub fn dispatch_collisions(
+        &mut self,
+        collisions: &[(Handle, Handle)],
+    ) -> Result<(), Error> {
+        let Some(game) = &self.game else {
+            return Ok(());
+        };
+
+        for &(a, b) in collisions {
+            let (owner_a, owner_b) = {
+                let world = self.world.borrow();
+                (world.owner(a), world.owner(b))
+            };
+
+            let (mine, other) = if owner_a == Some(self.module_id) {
+                (a, b)
+            } else if owner_b == Some(self.module_id) {
+                (b, a)
+            } else {
+                continue;
+            };
+
+            game.call_on_collision(&mut self.store, mine.bits(), other.bits())
+                .map_err(guest_error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Call the component's `on-timer` export for each of its due timers
+    pub fn dispatch_timers(&mut self, timers: &[Timer]) -> Result<(), Error> {
+        let Some(game) = &self.game else {
+            return Ok(());
+        };
+
+        for timer in timers {
+            if timer.module == self.module_id {
+                let handle = timer.handle.map_or(0, |h| h.bits());
+                game.call_on_timer(&mut self.store, handle, timer.callback)
+                    .map_err(guest_error)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn guest_error(error: wasmtime::Error) -> Error {
+    if error.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+        Error::BudgetExceeded
+    } else {
+        Error::Trap(error)
+    }
+}
+
+impl bindings::pipe_cleaner::engine::types::Host for ComponentState {}
+
+impl host::Host for ComponentState {
+    fn create_entity(&mut self) -> Option<u64> {
+        let mut world = self.world.borrow_mut();
+
+        if world.owned_count(self.module) >= self.max_entities {
+            return None;
+        }
+
+        world.create_entity(self.module).map(|handle| handle.bits())
+    }
+
+    fn get_entity(&mut self, handle: u64) -> Option<Entity> {
+        let handle = Handle::from_bits(handle)?;
+        self.world.borrow().entity(handle).map(Entity::from)
+    }
+
+    /// Only for the component's own entities, whatever
+    /// `Config::modify_foreign_entities` says, since a component can't ask
+    /// for the capability
+    fn write_entity_back(&mut self, handle: u64, entity: Entity) -> bool {
+        let Some(handle) = Handle::from_bits(handle) else {
+            return false;
+        };
+
+        let mut world = self.world.borrow_mut();
+
+        match wasm_entity::Entity::try_from(entity) {
+            Ok(entity) if world.owner(handle) == Some(self.module) => {
+                world.set_entity(handle, entity)
+            }
+            _ => false,
+        }
+    }
+
+    fn remove_entity(&mut self, handle: u64) -> bool {
+        let Some(handle) = Handle::from_bits(handle) else {
+            return false;
+        };
+
+        let mut world = self.world.borrow_mut();
+        world.owner(handle) == Some(self.module) && world.remove_entity(handle)
+    }
+
+    fn log(&mut self, level: WitLogLevel, message: String) {
+        let level = LogLevel::from(level);
+
+        if level <= self.log_level {
+            if level <= LogLevel::Warn {
+                eprintln!("[{}] {level}: {message}", self.name);
+            } else {
+                println!("[{}] {level}: {message}", self.name);
+            }
+        }
+    }
+
+    fn input(&mut self) -> InputState {
+        let input = self.world.borrow().input();
+
+        InputState {
+            held: input.held,
+            pressed: input.pressed,
+        }
+    }
+
+    fn query_entities(&mut self, query: EntityQuery) -> Vec<u64> {
+        let query = pipe_cleaner_shared::EntityQuery {
+            filters: query.filters,
+            model: query.model,
+            position: query.position.into(),
+            angle_range: query.angle_range,
+            depth_range: query.depth_range,
+        };
+
+        self.world
+            .borrow()
+            .query(&query, self.module)
+            .map(|handle| handle.bits())
+            .collect()
+    }
+
+    fn random(&mut self) -> u64 {
+        self.world.borrow_mut().random()
+    }
+
+    fn schedule(
+        &mut self,
+        handle: Option<u64>,
+        delay: f32,
+        callback: u32,
+    ) -> Option<u64> {
+        let handle = match handle {
+            Some(bits) => Some(Handle::from_bits(bits)?),
+            None => None,
+        };
+
+        self.world
+            .borrow_mut()
+            .schedule(self.module, handle, delay, callback)
+    }
+
+    fn cancel_timer(&mut self, id: u64) -> bool {
+        self.world.borrow_mut().cancel_timer(self.module, id)
+    }
+}
+
+impl From<WitLogLevel> for LogLevel {
+    fn from(level: WitLogLevel) -> Self {
+        match level {
+            WitLogLevel::Error => LogLevel::Error,
+            WitLogLevel::Warn => LogLevel::Warn,
+            WitLogLevel::Info => LogLevel::Info,
+            WitLogLevel::Debug => LogLevel::Debug,
+            WitLogLevel::Trace => LogLevel::Trace,
+        }
+    }
+}
+
+impl From<PipePosition> for pipe_cleaner_shared::PipePosition {
+    fn from(position: PipePosition) -> Self {
+        Self {
+            angle: position.angle,
+            depth: position.depth,
+        }
+    }
+}
+
+impl From<pipe_cleaner_shared::PipePosition> for PipePosition {
+    fn from(position: pipe_cleaner_shared::PipePosition) -> Self {
+        Self {
+            angle: position.angle,
+            depth: position.depth,
+        }
+    }
+}
+
+impl From<wasm_entity::Entity> for Entity {
+    fn from(entity: wasm_entity::Entity) -> Self {
+        let fields = entity.engine_fields;
+        let [vx, vy] = fields.velocity;
+        let [tx, ty] = fields.target_velocity;
+        let [r, g, b] = fields.color;
+
+        Entity {
+            engine_fields: EngineFields {
+                position: fields.position.into(),
+                velocity: (vx, vy),
+                target_velocity: (tx, ty),
+                max_acceleration: fields.max_acceleration,
+                max_speed: fields.max_speed,
+                color: (r, g, b),
+                model: fields.model,
+                collision_radius: fields.collision_radius,
+                collision_layers: fields.collision_layers,
+                collision_mask: fields.collision_mask,
+            },
+            game_fields: { entity.game_fields }.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<Entity> for wasm_entity::Entity {
+    type Error = ();
+
+    /// Fails if the game fields are the wrong length
+    fn try_from(entity: Entity) -> Result<Self, ()> {
+        let fields = entity.engine_fields;
+        let (vx, vy) = fields.velocity;
+        let (tx, ty) = fields.target_velocity;
+        let (r, g, b) = fields.color;
+
+        Ok(Self {
+            engine_fields: wasm_entity::EngineFields {
+                position: fields.position.into(),
+                velocity: [vx, vy],
+                target_velocity: [tx, ty],
+                max_acceleration: fields.max_acceleration,
+                max_speed: fields.max_speed,
+                color: [r, g, b],
+                model: fields.model,
+                collision_radius: fields.collision_radius,
+                collision_layers: fields.collision_layers,
+                collision_mask: fields.collision_mask,
+            },
+            game_fields: entity.game_fields.try_into().map_err(|_| ())?,
+        })
+    }
+}
@@ -1,16 +1,13 @@
-use crate::{World, visual};
+use crate::visual;
+use crate::world::World;
 use std::cell::{Ref, RefCell};
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
-const PIPE_RADIUS: f32 = 1.0;
+pub const PIPE_RADIUS: f32 = 1.0;
 
-#[derive(Clone, Copy)]
-pub struct PipePosition {
-    pub angle: f32,
-    pub depth: f32,
-}
+pub use pipe_cleaner_shared::PipePosition;
 
 pub type Think = dyn Fn(&mut World, EntRef);
 pub type EntRef = Rc<RefCell<Entity>>;
@@ -55,16 +52,55 @@ impl Entity {
     }
 }
 
+/// Transform from model space to world space for an object on the pipe wall
+#[rustfmt::skip]
+pub fn pipe_transform(position: PipePosition) -> visual::TransformMatrix {
+    let (sin, cos) = position.angle.sin_cos();
+
+    [
+        cos,  sin,  0f32, PIPE_RADIUS*cos,
+        sin, -cos,  0f32, PIPE_RADIUS*sin,
+        0f32, 0f32, 1f32, position.depth,
+    ]
+}
+
+/// Advance a position and angular velocity by `dt` seconds, accelerating
+/// towards the target angular velocity
+pub fn integrate(
+    position: &mut PipePosition,
+    velocity: &mut [f32; 2],
+    target_velocity: [f32; 2],
+    max_acceleration: f32,
+    dt: f32,
+) {
+    let [mut vel_angular, vel_depth] = *velocity;
+    let [targ_vel_angular, _] = target_velocity;
+
+    let accel = if targ_vel_angular > vel_angular {
+        max_acceleration
+    } else if targ_vel_angular < vel_angular {
+        -max_acceleration
+    } else {
+        0.0
+    };
+
+    position.angle += 0.5 * dt * dt * accel + dt * vel_angular;
+    position.depth += dt * vel_depth;
+
+    if targ_vel_angular > vel_angular {
+        vel_angular += dt * max_acceleration;
+        vel_angular = vel_angular.min(targ_vel_angular);
+    } else if targ_vel_angular < vel_angular {
+        vel_angular -= dt * max_acceleration;
+        vel_angular = vel_angular.max(targ_vel_angular);
+    }
+
+    *velocity = [vel_angular, vel_depth];
+}
+
 impl visual::Instance for Entity {
-    #[rustfmt::skip]
-    fn transform(&self) -> [f32; 12] {
-        let (sin, cos) = self.position.angle.sin_cos();
-
-        [
-            cos,  sin,  0f32, PIPE_RADIUS*cos,
-            sin, -cos,  0f32, PIPE_RADIUS*sin,
-            0f32, 0f32, 1f32, self.position.depth,
-        ]
+    fn transform(&self) -> visual::TransformMatrix {
+        pipe_transform(self.position)
     }
 
     fn color(&self) -> [f32; 3] {
@@ -99,20 +135,12 @@ impl HashEnt {
 
 impl Eq for HashEnt {}
 
+#[derive(Default)]
 pub struct Manager {
     next_id: u64,
     pub entities: HashSet<HashEnt>,
 }
 
-impl Default for Manager {
-    fn default() -> Self {
-        Manager {
-            next_id: 0u64,
-            entities: HashSet::new(),
-        }
-    }
-}
-
 impl Manager {
     pub fn iter(&self) -> impl Iterator<Item = EntRef> + '_ {
         self.entities.iter().map(|HashEnt(ent)| ent.clone())
@@ -131,7 +159,7 @@ impl Manager {
         self.next_id += 1;
         let ent = Rc::new(RefCell::new(ent));
         self.entities.insert(HashEnt(Rc::clone(&ent)));
-        return ent;
+        ent
     }
 
     pub fn remove(&mut self, ent: &EntRef) {
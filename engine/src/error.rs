@@ -0,0 +1,123 @@
+use crate::manifest::Capability;
+use crate::wasm::PanicReport;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The wasm engine could not be created with the requested settings
+    Engine(wasmtime::Error),
+    /// The game module file does not exist
+    ModuleNotFound(PathBuf),
+    /// The game module file exists but could not be read
+    ModuleRead(PathBuf, io::Error),
+    /// The game module is not valid wasm or failed to compile
+    Compile(wasmtime::Error),
+    /// Host functions could not be defined, or the module's imports could
+    /// not be satisfied
+    Link(wasmtime::Error),
+    /// The module lacks a required export, or it has the wrong type
+    MissingExport(String),
+    /// The module was built against a different version of the engine
+    /// interface, or (if `found` is `None`) does not declare one
+    AbiMismatch { expected: u32, found: Option<u32> },
+    /// The module's `pipecleaner_manifest` section is malformed
+    InvalidManifest(String),
+    /// The module's manifest asks for a capability the host doesn't allow
+    CapabilityDenied(Capability),
+    /// A module with the same name is already loaded
+    DuplicateModule(String),
+    /// The guest trapped without leaving a panic report
+    Trap(wasmtime::Error),
+    /// The guest panicked
+    GuestPanic(PanicReport),
+    /// The guest ran past its execution budget for a single call
+    BudgetExceeded,
+    /// A snapshot could not be written, or could not be read back into the
+    /// running session
+    Snapshot(io::Error),
+    /// Could not get a raw handle to the window
+    WindowHandle(String),
+    /// Could not create a wgpu surface for the window
+    Surface(wgpu::CreateSurfaceError),
+    /// No graphics adapter compatible with the surface was found
+    NoAdapter,
+    /// The graphics adapter refused to provide a device
+    Device(wgpu::RequestDeviceError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Engine(e) => write!(f, "Failed to create engine: {e}"),
+            Error::ModuleNotFound(path) => {
+                write!(f, "Module not found: {}", path.display())
+            }
+            Error::ModuleRead(path, e) => {
+                write!(f, "Failed to read module {}: {e}", path.display())
+            }
+            Error::Compile(e) => write!(f, "Failed to compile module: {e}"),
+            Error::Link(e) => write!(f, "Failed to link module: {e}"),
+            Error::MissingExport(name) => {
+                write!(f, "Missing or mistyped export: {name}")
+            }
+            Error::AbiMismatch {
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "Module uses ABI version {found}, but the engine requires \
+                 version {expected}"
+            ),
+            Error::AbiMismatch {
+                expected,
+                found: None,
+            } => write!(
+                f,
+                "Module does not export PIPECLEANER_abi_version; rebuild it \
+                 against ABI version {expected}"
+            ),
+            Error::InvalidManifest(e) => write!(f, "Invalid manifest: {e}"),
+            Error::CapabilityDenied(capability) => {
+                write!(f, "Module needs the {capability} capability")
+            }
+            Error::DuplicateModule(name) => {
+                write!(f, "A module named {name} is already loaded")
+            }
+            Error::Trap(e) => write!(f, "Guest trapped: {e}"),
+            Error::GuestPanic(report) => {
+                write!(f, "Guest panicked: {}", report.message)?;
+
+                if !report.complete {
+                    write!(f, " (report incomplete)")?;
+                }
+
+                Ok(())
+            }
+            Error::BudgetExceeded => {
+                write!(f, "Guest exceeded its execution budget")
+            }
+            Error::Snapshot(e) => write!(f, "Snapshot failed: {e}"),
+            Error::WindowHandle(e) => write!(f, "Bad window handle: {e}"),
+            Error::Surface(e) => write!(f, "Failed to create surface: {e}"),
+            Error::NoAdapter => write!(f, "No adapter found"),
+            Error::Device(e) => write!(f, "Failed to request device: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ModuleRead(_, e) | Error::Snapshot(e) => Some(e),
+            Error::Engine(e)
+            | Error::Compile(e)
+            | Error::Link(e)
+            | Error::Trap(e) => Some(&**e),
+            Error::Surface(e) => Some(e),
+            Error::Device(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Runs game modules without a window or renderer, for testing them from
+//! `cargo test`.
+//!
+//! ```no_run
+//! use pipe_cleaner_engine::harness::Harness;
+//! use pipe_cleaner_engine::wasm::Config;
+//! use pipe_cleaner_shared::Button;
+//!
+//! let mut harness =
+//!     Harness::new(&["base_game.wasm"], 1, Config::default()).unwrap();
+//! harness.run(60, &[Button::Right]);
+//! assert!(harness.entities().len() > 0);
+//! ```
+
+use crate::Guests;
+use crate::cli::{DEFAULT_RINGS, DEFAULT_TICK_RATE};
+use crate::error::Error;
+use crate::visual;
+use crate::wasm::Config;
+use crate::wasm_entity::Entity;
+use crate::world::World;
+use pipe_cleaner_shared::Button;
+use std::path::Path;
+
+pub struct Harness {
+    world: World,
+    guests: Guests,
+    /// Seconds of game time covered by each tick
+    tick: f64,
+    tick_ct: u64,
+}
+
+impl Harness {
+    /// Load each module in order and run its `PIPECLEANER_init`, with the
+    /// guests' random numbers seeded by `seed`.  Fails on the first module
+    /// that doesn't load.
+    pub fn new<P: AsRef<Path>>(
+        modules: &[P],
+        seed: u64,
+        config: Config,
+    ) -> Result<Self, Error> {
+        let mut builder = visual::ManagerBuilder::new();
+        let world = World::new(&mut builder, DEFAULT_RINGS);
+        let mut guests = Guests::default();
+
+        {
+            let wasm_world = world.wasm_world();
+            let mut wasm_world = wasm_world.borrow_mut();
+            wasm_world.seed(seed);
+            wasm_world.open_model_registration(builder.model_count());
+        }
+
+        for path in modules {
+            guests.load(path.as_ref(), world.wasm_world(), config.clone())?;
+        }
+
+        // Nothing is drawn, but models still have to be taken so guests
+        // see the same ids as in a windowed run
+        for mesh in world.wasm_world().borrow_mut().take_models() {
+            builder.register_model(mesh.thicken());
+        }
+
+        Ok(Self {
+            world,
+            guests,
+            tick: DEFAULT_TICK_RATE.recip(),
+            tick_ct: 0,
+        })
+    }
+
+    /// Run at `tick_rate` ticks per second of game time instead of the
+    /// default
+    pub fn with_tick_rate(mut self, tick_rate: f64) -> Self {
+        self.tick = tick_rate.recip();
+        self
+    }
+
+    /// Run one tick with `held` buttons held down
+    pub fn tick(&mut self, held: &[Button]) {
+        let held = held.iter().fold(0, |bits, &button| bits | button as u32);
+        crate::run_tick(&mut self.world, &mut self.guests, held, self.tick);
+        self.tick_ct += 1;
+    }
+
+    /// Run `ticks` ticks with the same buttons held down throughout
+    pub fn run(&mut self, ticks: u64, held: &[Button]) {
+        for _ in 0..ticks {
+            self.tick(held);
+        }
+    }
+
+    /// Run one tick for each entry of `script`, holding that entry's buttons
+    pub fn run_script<'a>(
+        &mut self,
+        script: impl IntoIterator<Item = &'a [Button]>,
+    ) {
+        for held in script {
+            self.tick(held);
+        }
+    }
+
+    /// Copies of the guest entities as they are now
+    pub fn entities(&self) -> Vec<Entity> {
+        self.world
+            .wasm_world()
+            .borrow()
+            .entity_iter()
+            .copied()
+            .collect()
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Names of the modules still loaded.  A module that traps or runs out
+    /// of fuel is unloaded, as it would be in a normal run.
+    pub fn loaded(&self) -> Vec<&str> {
+        self.guests.names()
+    }
+
+    pub fn tick_ct(&self) -> u64 {
+        self.tick_ct
+    }
+}
@@ -0,0 +1,188 @@
+pub mod cache;
+pub mod cli;
+#[cfg(feature = "component")]
+pub mod component;
+pub mod entity;
+pub mod error;
+pub mod harness;
+pub mod manifest;
+pub mod rng;
+pub mod snapshot;
+pub mod storage;
+pub mod visual;
+pub mod wasm;
+pub mod wasm_entity;
+pub mod world;
+
+use error::Error;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use world::{ModuleId, WasmWorld, World};
+
+/// Every loaded game module
+#[derive(Default)]
+pub struct Guests {
+    pub hosts: Vec<wasm::Host>,
+    /// Modules built as components, which are kept apart because they can't
+    /// be reloaded or snapshotted
+    #[cfg(feature = "component")]
+    pub components: Vec<component::Host>,
+}
+
+impl Guests {
+    /// Load the module at `path` and run its init export, as a component if
+    /// it is one.  Fails if a module with the same name is already loaded.
+    pub fn load(
+        &mut self,
+        path: &Path,
+        world: Rc<RefCell<WasmWorld>>,
+        config: wasm::Config,
+    ) -> Result<(), Error> {
+        #[cfg(feature = "component")]
+        if component::is_component(path) {
+            self.check_name(&wasm::file_name(path))?;
+            let mut host = component::Host::new(path, world, config)?;
+            host.run()?;
+            println!("Loaded component {}", host.name());
+            self.components.push(host);
+            return Ok(());
+        }
+
+        self.check_name(&wasm::module_name(path)?)?;
+        let mut host = wasm::Host::new(path, world, config)?;
+        host.run()?;
+
+        if let Some(manifest) = host.manifest() {
+            println!("Loaded {manifest}");
+        }
+
+        self.hosts.push(host);
+        Ok(())
+    }
+
+    /// Names of the modules still loaded
+    pub fn names(&self) -> Vec<&str> {
+        let names = self.hosts.iter().map(wasm::Host::name);
+
+        #[cfg(feature = "component")]
+        let names =
+            names.chain(self.components.iter().map(component::Host::name));
+
+        names.collect()
+    }
+
+    /// Guests are told apart by name in logs and storage, so each name can
+    /// only be loaded once.  Checked before the module is registered with
+    /// the world, so a refused module leaves no trace in snapshots.
+    fn check_name(&self, name: &str) -> Result<(), Error> {
+        if self.names().contains(&name) {
+            Err(Error::DuplicateModule(name.to_owned()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Advance the guests and world by one tick of `dt` seconds, with `held` as
+/// the bitmask of buttons held down.  Guests that fail are unloaded.
+pub fn run_tick(world: &mut World, guests: &mut Guests, held: u32, dt: f64) {
+    world.wasm_world().borrow_mut().update_input(held);
+
+    for host in guests.hosts.iter_mut() {
+        match host.poll_reload() {
+            Ok(true) => println!("Reloaded {}", host.name()),
+            Ok(false) => {}
+            Err(e) => eprintln!("Reload of {} failed: {e}", host.name()),
+        }
+    }
+
+    // Each guest gets one fuel budget for everything it runs this tick
+    for host in guests.hosts.iter_mut() {
+        host.refuel();
+    }
+    #[cfg(feature = "component")]
+    for host in guests.components.iter_mut() {
+        host.refuel();
+    }
+
+    let wasm_world = world.wasm_world();
+
+    // Messages posted during the previous tick
+    let messages = wasm_world.borrow_mut().take_messages();
+    run_guests(&wasm_world, &mut guests.hosts, |host| {
+        host.dispatch_messages(&messages)
+    });
+
+    run_guests(&wasm_world, &mut guests.hosts, |host| {
+        host.update(dt as f32)
+    });
+    #[cfg(feature = "component")]
+    run_guests(&wasm_world, &mut guests.components, |host| {
+        host.update(dt as f32)
+    });
+    run_guests(&wasm_world, &mut guests.hosts, |host| host.think());
+    world.update(dt);
+
+    let collisions = wasm_world.borrow().collisions();
+    run_guests(&wasm_world, &mut guests.hosts, |host| {
+        host.dispatch_collisions(&collisions)
+    });
+    #[cfg(feature = "component")]
+    run_guests(&wasm_world, &mut guests.components, |host| {
+        host.dispatch_collisions(&collisions)
+    });
+
+    let timers = wasm_world.borrow_mut().take_due_timers();
+    run_guests(&wasm_world, &mut guests.hosts, |host| {
+        host.dispatch_timers(&timers)
+    });
+    #[cfg(feature = "component")]
+    run_guests(&wasm_world, &mut guests.components, |host| {
+        host.dispatch_timers(&timers)
+    });
+}
+
+/// Something running a game module
+trait Guest {
+    fn name(&self) -> &str;
+    fn module_id(&self) -> ModuleId;
+}
+
+impl Guest for wasm::Host {
+    fn name(&self) -> &str {
+        wasm::Host::name(self)
+    }
+
+    fn module_id(&self) -> ModuleId {
+        wasm::Host::module_id(self)
+    }
+}
+
+#[cfg(feature = "component")]
+impl Guest for component::Host {
+    fn name(&self) -> &str {
+        component::Host::name(self)
+    }
+
+    fn module_id(&self) -> ModuleId {
+        component::Host::module_id(self)
+    }
+}
+
+/// Run `f` on each guest, unloading any that fail along with everything
+/// they left in the world
+fn run_guests<G: Guest>(
+    world: &RefCell<WasmWorld>,
+    guests: &mut Vec<G>,
+    mut f: impl FnMut(&mut G) -> Result<(), Error>,
+) {
+    guests.retain_mut(|guest| match f(guest) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{}: {e}", guest.name());
+            world.borrow_mut().unload_module(guest.module_id());
+            false
+        }
+    });
+}
@@ -1,34 +1,47 @@
-mod entity;
-mod visual;
-mod wasm;
-mod wasm_entity;
-mod world;
-
-use entity::{EntRef, PipePosition};
+use pipe_cleaner_engine::cli::{self, Cli};
+use pipe_cleaner_engine::entity::{EntRef, PipePosition};
+use pipe_cleaner_engine::visual::{self, geo};
+use pipe_cleaner_engine::world::World;
+use pipe_cleaner_engine::{Guests, snapshot, wasm};
+use pipe_cleaner_shared::Button;
 use sdl3::event::{Event, WindowEvent};
 use sdl3::keyboard::Keycode;
 use std::rc::Rc;
 use std::thread::sleep;
-use std::time::Duration;
-use visual::geo;
-use world::World;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub const FRAME_DURATION: f64 = 1f64 / 120f64;
-pub const FRAME_DURATION_F32: f32 = FRAME_DURATION as f32;
+/// Keys held down by the player
+#[derive(Default)]
+struct Controls {
+    left: f32,
+    right: f32,
+    fire: bool,
+}
 
 fn main() -> Result<(), String> {
-    let res =
-        wasm::Host::new("target/wasm32-unknown-unknown/release/base_game.wasm");
+    let cli = Cli::parse(std::env::args().skip(1))?;
+
+    if cli.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    if cli.list {
+        for path in &cli.modules {
+            let manifest = wasm::read_manifest(path);
+            let path = path.display();
 
-    match res {
-        Ok(host) => {
-            if let Err(e) = host.run() {
-                eprintln!("{e}");
+            match manifest {
+                Ok(Some(manifest)) => println!("{path}: {manifest}"),
+                Ok(None) => println!("{path}: no manifest"),
+                Err(e) => println!("{path}: {e}"),
             }
         }
-        Err(e) => eprintln!("{e}"),
+
+        return Ok(());
     }
 
+    let tick = cli.tick_duration();
     let cube_vertices = geo::cube_pts();
     let cube_indices = geo::cube_indices();
     let bullet_vertices = geo::bullet_pts(0.2);
@@ -47,15 +60,45 @@ fn main() -> Result<(), String> {
     .thicken();
 
     let mut vis_mgr_builder = visual::ManagerBuilder::new();
-    let mut world = World::new(&mut vis_mgr_builder, 20);
+    let mut world = World::new(&mut vis_mgr_builder, cli.rings);
     let cube_model = vis_mgr_builder.register_model(cube_mesh);
     let bullet_model = vis_mgr_builder.register_model(bullet_mesh);
 
+    // Without a seed, pick one from the clock and print it so the run can be
+    // repeated
+    let seed = cli.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64)
+    });
+
+    println!("Seed: {seed}");
+    world.wasm_world().borrow_mut().seed(seed);
+
+    world
+        .wasm_world()
+        .borrow_mut()
+        .open_model_registration(vis_mgr_builder.model_count());
+
+    let mut guests = Guests::default();
+
+    for path in &cli.modules {
+        let config = wasm::Config::default();
+
+        if let Err(e) = guests.load(path, world.wasm_world(), config) {
+            eprintln!("{}: {e}", path.display());
+        }
+    }
+
+    for mesh in world.wasm_world().borrow_mut().take_models() {
+        vis_mgr_builder.register_model(mesh.thicken());
+    }
+
     let bullet_think = move |world: &mut World, bullet: EntRef| {
         let countdown = bullet.borrow().countdown;
 
         if countdown > 0.0 {
-            bullet.borrow_mut().countdown -= FRAME_DURATION;
+            bullet.borrow_mut().countdown -= tick;
         } else {
             world.remove_entity(bullet);
         }
@@ -63,7 +106,7 @@ fn main() -> Result<(), String> {
 
     let player_think = move |world: &mut World, player: EntRef| {
         if player.borrow().countdown > 0.0 {
-            player.borrow_mut().countdown -= FRAME_DURATION;
+            player.borrow_mut().countdown -= tick;
         } else if player.borrow().fire {
             let mut muzzle = player.borrow().position;
             muzzle.depth += 0.05;
@@ -105,35 +148,60 @@ fn main() -> Result<(), String> {
         player.think = Rc::new(player_think);
     }
 
+    if cli.restore {
+        let res = snapshot::load(
+            &cli.snapshot,
+            &mut world.wasm_world().borrow_mut(),
+            &mut guests,
+        );
+
+        match res {
+            Ok(()) => println!("Restored {}", cli.snapshot.display()),
+            Err(e) => return Err(format!("{}: {e}", cli.snapshot.display())),
+        }
+    }
+
+    let frame_duration = Duration::from_secs_f64(tick);
+    let mut tick_ct = 0;
+    let ticks_left = |tick_ct| cli.ticks.is_none_or(|ticks| tick_ct < ticks);
+
+    if cli.headless {
+        let controls = Controls::default();
+
+        while ticks_left(tick_ct) {
+            run_tick(&mut world, &mut guests, &player, &controls, tick);
+            tick_ct += 1;
+            sleep(frame_duration);
+        }
+
+        return Ok(());
+    }
+
     let sdl_context = sdl3::init().map_err(|e| e.to_string())?;
     let video_subsystem = sdl_context.video().map_err(|e| e.to_string())?;
+    let (mut w, mut h) = cli.size;
+    let mut window_builder = video_subsystem.window("Pipecleaner", w, h);
+    window_builder.position_centered().resizable();
 
-    let window = video_subsystem
-        .window("Pipecleaner", 800, 600)
-        .position_centered()
-        .resizable()
-        .build()
-        .map_err(|e| e.to_string())?;
+    if cli.fullscreen {
+        window_builder.fullscreen();
+    }
 
+    let window = window_builder.build().map_err(|e| e.to_string())?;
     let main_window_id = window.id();
 
     let mut rend =
-        visual::Renderer::new(&window, 90.0, vis_mgr_builder).map_err(|e| e)?;
+        visual::Renderer::new(&window, cli.fov, cli.backends, vis_mgr_builder)
+            .map_err(|e| e.to_string())?;
 
     let mut event_pump = sdl_context.event_pump().map_err(|e| e.to_string())?;
-    let frame_duration = Duration::from_secs_f64(FRAME_DURATION);
+    let mut controls = Controls::default();
 
-    let mut w = 800u32;
-    let mut h = 600u32;
-    let mut left = 0f32;
-    let mut right = 0f32;
-    let mut fire = false;
-
-    'running: loop {
+    while ticks_left(tick_ct) {
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => {
-                    break 'running;
+                    return Ok(());
                 }
                 Event::Window {
                     window_id,
@@ -147,38 +215,89 @@ fn main() -> Result<(), String> {
                     keycode: Some(k), ..
                 } => {
                     if k == Keycode::A {
-                        left = 1.0;
+                        controls.left = 1.0;
                     } else if k == Keycode::D {
-                        right = 1.0;
+                        controls.right = 1.0;
                     } else if k == Keycode::Space {
-                        fire = true;
+                        controls.fire = true;
+                    } else if k == Keycode::F5 {
+                        let res = snapshot::save(
+                            &cli.snapshot,
+                            &world.wasm_world().borrow(),
+                            &mut guests,
+                        );
+
+                        match res {
+                            Ok(()) => {
+                                println!("Saved {}", cli.snapshot.display())
+                            }
+                            Err(e) => eprintln!("{e}"),
+                        }
+                    } else if k == Keycode::F9 {
+                        let res = snapshot::load(
+                            &cli.snapshot,
+                            &mut world.wasm_world().borrow_mut(),
+                            &mut guests,
+                        );
+
+                        match res {
+                            Ok(()) => {
+                                println!("Loaded {}", cli.snapshot.display())
+                            }
+                            Err(e) => eprintln!("{e}"),
+                        }
                     }
                 }
                 Event::KeyUp {
                     keycode: Some(k), ..
                 } => {
                     if k == Keycode::A {
-                        left = 0.0;
+                        controls.left = 0.0;
                     } else if k == Keycode::D {
-                        right = 0.0;
+                        controls.right = 0.0;
                     } else if k == Keycode::Space {
-                        fire = false;
+                        controls.fire = false;
                     }
                 }
                 _ => {}
             };
         }
 
-        {
-            let mut player = player.borrow_mut();
-            player.target_velocity[0] = (right - left) * player.max_speed;
-            player.fire = fire;
-        }
-
-        world.update();
+        run_tick(&mut world, &mut guests, &player, &controls, tick);
+        tick_ct += 1;
         rend.render((w, h), world.geometry());
         sleep(frame_duration);
     }
 
     Ok(())
 }
+
+/// Advance the native player, guests and world by one tick of `dt` seconds
+fn run_tick(
+    world: &mut World,
+    guests: &mut Guests,
+    player: &EntRef,
+    controls: &Controls,
+    dt: f64,
+) {
+    {
+        let mut player = player.borrow_mut();
+        player.target_velocity[0] =
+            (controls.right - controls.left) * player.max_speed;
+        player.fire = controls.fire;
+    }
+
+    let mut held = 0;
+
+    for (button, down) in [
+        (Button::Left, controls.left > 0.0),
+        (Button::Right, controls.right > 0.0),
+        (Button::Fire, controls.fire),
+    ] {
+        if down {
+            held |= button as u32;
+        }
+    }
+
+    pipe_cleaner_engine::run_tick(world, guests, held, dt);
+}
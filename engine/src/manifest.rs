@@ -0,0 +1,343 @@
+use crate::error::Error;
+use pipe_cleaner_shared::{ABI_VERSION, MANIFEST_SECTION, capability};
+use std::fmt;
+
+const WASM_HEADER_SZ: usize = 8;
+const CUSTOM_SECTION_ID: u8 = 0;
+const MAX_NAME_LEN: usize = 64;
+
+/// Something a module has to ask for in its manifest before the engine lets
+/// it happen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// Save values with the storage imports
+    Storage,
+    /// Overwrite and remove entities created by other modules, if the host
+    /// is also configured to allow it
+    ForeignEntities,
+}
+
+impl Capability {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            capability::STORAGE => Some(Capability::Storage),
+            capability::FOREIGN_ENTITIES => Some(Capability::ForeignEntities),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Storage => capability::STORAGE,
+            Capability::ForeignEntities => capability::FOREIGN_ENTITIES,
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Description of a game module, read from its `pipecleaner_manifest` custom
+/// section.  The section is UTF-8 text with one `key=value` pair per line;
+/// `capability` may appear any number of times and unknown keys are ignored.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub abi_version: u32,
+    pub capabilities: Vec<Capability>,
+}
+
+impl Manifest {
+    /// Parse the manifest out of a module's bytes, if it has one.  Doesn't
+    /// check that the module is compatible; see `validate`.
+    pub fn from_module(bytes: &[u8]) -> Result<Option<Self>, Error> {
+        let sections = custom_sections(bytes)
+            .ok_or_else(|| invalid("Module sections are malformed".into()))?;
+
+        let mut section = None;
+
+        for (name, contents) in sections {
+            if name == MANIFEST_SECTION.as_bytes() {
+                if section.is_some() {
+                    return Err(invalid("More than one manifest".into()));
+                }
+
+                section = Some(contents);
+            }
+        }
+
+        section.map(Self::parse).transpose()
+    }
+
+    /// Refuse modules built for another ABI version
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.abi_version == ABI_VERSION {
+            Ok(())
+        } else {
+            Err(Error::AbiMismatch {
+                expected: ABI_VERSION,
+                found: Some(self.abi_version),
+            })
+        }
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    fn parse(section: &[u8]) -> Result<Self, Error> {
+        let text = str::from_utf8(section)
+            .map_err(|_| invalid("Manifest is not UTF-8".into()))?;
+
+        let mut name = None;
+        let mut version = None;
+        let mut author = String::new();
+        let mut abi_version = None;
+        let mut capabilities = Vec::new();
+
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("Bad line {line:?}")))?;
+
+            match key {
+                "name" => name = Some(value.to_owned()),
+                "version" => version = Some(value.to_owned()),
+                "author" => author = value.to_owned(),
+                "abi" => {
+                    abi_version = Some(value.parse().map_err(|_| {
+                        invalid(format!("Bad ABI version {value:?}"))
+                    })?)
+                }
+                "capability" => {
+                    let capability =
+                        Capability::from_name(value).ok_or_else(|| {
+                            invalid(format!("Unknown capability {value:?}"))
+                        })?;
+
+                    if !capabilities.contains(&capability) {
+                        capabilities.push(capability);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let name = name.ok_or_else(|| invalid("Missing name".into()))?;
+
+        // The name is used as a directory name for the module's saved data
+        if name.is_empty()
+            || name.len() > MAX_NAME_LEN
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+        {
+            return Err(invalid(format!("Bad name {name:?}")));
+        }
+
+        Ok(Manifest {
+            name,
+            version: version
+                .ok_or_else(|| invalid("Missing version".into()))?,
+            author,
+            abi_version: abi_version
+                .ok_or_else(|| invalid("Missing ABI version".into()))?,
+            capabilities,
+        })
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.version)?;
+
+        if !self.author.is_empty() {
+            write!(f, " by {}", self.author)?;
+        }
+
+        write!(f, " (ABI {}", self.abi_version)?;
+
+        for capability in &self.capabilities {
+            write!(f, ", {capability}")?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::InvalidManifest(message)
+}
+
+/// Name and contents of each custom section in a wasm binary, or `None` if
+/// the binary is cut short or malformed
+fn custom_sections(bytes: &[u8]) -> Option<Vec<(&[u8], &[u8])>> {
+    let mut rest = bytes.get(WASM_HEADER_SZ..)?;
+    let mut sections = Vec::new();
+
+    while let Some((&id, after_id)) = rest.split_first() {
+        let (len, after_len) = read_leb128(after_id)?;
+        let (contents, after) = after_len.split_at_checked(len as usize)?;
+        rest = after;
+
+        if id == CUSTOM_SECTION_ID {
+            let (name_len, after_name_len) = read_leb128(contents)?;
+            sections.push(after_name_len.split_at_checked(name_len as usize)?);
+        }
+    }
+
+    Some(sections)
+}
+
+/// Unsigned LEB128 number at the start of `bytes`, and the bytes after it
+fn read_leb128(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let mut value = 0u32;
+
+    for (idx, &byte) in bytes.iter().enumerate().take(5) {
+        // The fifth byte only has room for the top four bits
+        if idx == 4 && byte > 0x0f {
+            return None;
+        }
+
+        value |= u32::from(byte & 0x7f) << (7 * idx);
+
+        if byte & 0x80 == 0 {
+            return Some((value, &bytes[idx + 1..]));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: [u8; WASM_HEADER_SZ] = *b"\0asm\x01\0\0\0";
+
+    /// A module with one custom section per `(name, contents)` pair, and an
+    /// empty type section in between to make sure it's skipped
+    fn module(sections: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut bytes = HEADER.to_vec();
+
+        for (name, contents) in sections {
+            let len = 1 + name.len() + contents.len();
+            bytes.extend([CUSTOM_SECTION_ID, len as u8, name.len() as u8]);
+            bytes.extend(name.as_bytes());
+            bytes.extend(*contents);
+            bytes.extend([1, 1, 0]);
+        }
+
+        bytes
+    }
+
+    fn manifest(text: &str) -> Vec<u8> {
+        module(&[(MANIFEST_SECTION, text.as_bytes())])
+    }
+
+    #[test]
+    fn leb128() {
+        assert_eq!(read_leb128(&[0x00, 0xff]), Some((0, &[0xff][..])));
+        assert_eq!(read_leb128(&[0xe5, 0x8e, 0x26]), Some((624_485, &[][..])));
+        assert_eq!(
+            read_leb128(&[0xff, 0xff, 0xff, 0xff, 0x0f]),
+            Some((u32::MAX, &[][..]))
+        );
+    }
+
+    #[test]
+    fn leb128_rejects_truncated_and_oversized() {
+        assert_eq!(read_leb128(&[]), None);
+        assert_eq!(read_leb128(&[0x80, 0x80]), None);
+        assert_eq!(read_leb128(&[0xff, 0xff, 0xff, 0xff, 0x10]), None);
+        assert_eq!(read_leb128(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]), None);
+    }
+
+    #[test]
+    fn parses_manifest() {
+        let text = format!(
+            "name=demo\nversion=1.2\nauthor=someone\nabi={ABI_VERSION}\n\
+             capability=storage\ncapability=storage\nunknown=ignored\n"
+        );
+        let manifest =
+            Manifest::from_module(&manifest(&text)).unwrap().unwrap();
+
+        assert_eq!(manifest.name, "demo");
+        assert_eq!(manifest.version, "1.2");
+        assert_eq!(manifest.author, "someone");
+        assert_eq!(manifest.capabilities, [Capability::Storage]);
+        assert!(manifest.validate().is_ok());
+    }
+
+    #[test]
+    fn module_without_manifest() {
+        let bytes = module(&[("name", &[0])]);
+        assert!(Manifest::from_module(&bytes).unwrap().is_none());
+        assert!(Manifest::from_module(&HEADER).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_bad_manifests() {
+        for text in [
+            "version=1\nabi=2",
+            "name=demo\nabi=2",
+            "name=demo\nversion=1",
+            "name=demo\nversion=1\nabi=two",
+            "name=../up\nversion=1\nabi=2",
+            "name=\nversion=1\nabi=2",
+            "name=demo\nversion=1\nabi=2\ncapability=root",
+            "name=demo\nversion=1\nabi=2\nno equals sign",
+        ] {
+            assert!(
+                matches!(
+                    Manifest::from_module(&manifest(text)),
+                    Err(Error::InvalidManifest(_))
+                ),
+                "{text:?}"
+            );
+        }
+
+        let bytes = module(&[(MANIFEST_SECTION, &[0xff])]);
+        assert!(Manifest::from_module(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_manifests() {
+        let text: &[u8] = b"name=demo\nversion=1\nabi=2";
+        let bytes =
+            module(&[(MANIFEST_SECTION, text), (MANIFEST_SECTION, text)]);
+        assert!(Manifest::from_module(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_sections() {
+        let bytes = manifest("name=demo\nversion=1\nabi=2");
+
+        // Cut off partway through each section and inside the header
+        for len in [4, WASM_HEADER_SZ + 1, bytes.len() - 4] {
+            assert!(Manifest::from_module(&bytes[..len]).is_err(), "{len}");
+        }
+
+        // Custom section whose name runs past its end
+        let mut bytes = HEADER.to_vec();
+        bytes.extend([CUSTOM_SECTION_ID, 2, 5, b'a']);
+        assert!(Manifest::from_module(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_other_abi_versions() {
+        let text = format!("name=demo\nversion=1\nabi={}", ABI_VERSION + 1);
+        let manifest =
+            Manifest::from_module(&manifest(&text)).unwrap().unwrap();
+
+        assert!(matches!(
+            manifest.validate(),
+            Err(Error::AbiMismatch { found: Some(_), .. })
+        ));
+    }
+}
@@ -0,0 +1,149 @@
+//! Save states of the guest side of a game session: the wasm world and each
+//! host's instance.  Native entities aren't included.
+//!
+//! A snapshot file is the magic bytes, a format version, the wasm world and
+//! then each host in load order.  Numbers are little-endian.  Restoring
+//! requires the same modules to be loaded in the same order.  Sessions with
+//! component modules loaded can't be snapshotted.
+
+use crate::Guests;
+use crate::error::Error;
+use crate::world::WasmWorld;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"PCSNAPSH";
+/// Bump this whenever anything written to a snapshot changes
+const VERSION: u32 = 2;
+
+pub fn save(
+    path: &Path,
+    world: &WasmWorld,
+    guests: &mut Guests,
+) -> Result<(), Error> {
+    check_supported(guests)?;
+
+    let hosts = &mut guests.hosts;
+    let file = File::create(path).map_err(Error::Snapshot)?;
+    let mut w = BufWriter::new(file);
+
+    w.write_all(MAGIC).map_err(Error::Snapshot)?;
+    write_u32(&mut w, VERSION).map_err(Error::Snapshot)?;
+    world.snapshot(&mut w).map_err(Error::Snapshot)?;
+    write_u32(&mut w, hosts.len() as u32).map_err(Error::Snapshot)?;
+
+    for host in hosts.iter_mut() {
+        host.snapshot(&mut w)?;
+    }
+
+    w.flush().map_err(Error::Snapshot)
+}
+
+/// Restore a snapshot written by `save`.  If this fails part way through,
+/// the world and hosts may be left half restored.
+pub fn load(
+    path: &Path,
+    world: &mut WasmWorld,
+    guests: &mut Guests,
+) -> Result<(), Error> {
+    check_supported(guests)?;
+
+    let hosts = &mut guests.hosts;
+    let file = File::open(path).map_err(Error::Snapshot)?;
+    let mut r = BufReader::new(file);
+
+    let mut magic = [0; MAGIC.len()];
+    r.read_exact(&mut magic).map_err(Error::Snapshot)?;
+
+    if &magic != MAGIC {
+        return Err(Error::Snapshot(invalid("Not a snapshot file")));
+    }
+
+    let version = read_u32(&mut r).map_err(Error::Snapshot)?;
+
+    if version != VERSION {
+        return Err(Error::Snapshot(invalid(format!(
+            "Snapshot format version {version} isn't supported"
+        ))));
+    }
+
+    world.restore(&mut r).map_err(Error::Snapshot)?;
+
+    let host_ct = read_u32(&mut r).map_err(Error::Snapshot)?;
+
+    if host_ct as usize != hosts.len() {
+        return Err(Error::Snapshot(invalid(format!(
+            "Snapshot has {host_ct} modules, but {} are loaded",
+            hosts.len()
+        ))));
+    }
+
+    for host in hosts.iter_mut() {
+        host.restore(&mut r)?;
+    }
+
+    Ok(())
+}
+
+/// Component guests' state isn't saved, and restoring the world would pull
+/// their entities and timers out from under them
+fn check_supported(guests: &Guests) -> Result<(), Error> {
+    #[cfg(feature = "component")]
+    if !guests.components.is_empty() {
+        return Err(Error::Snapshot(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Component modules can't be snapshotted",
+        )));
+    }
+
+    #[cfg(not(feature = "component"))]
+    let _ = guests;
+
+    Ok(())
+}
+
+pub fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    error: E,
+) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+pub fn write_u32(w: &mut impl Write, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+pub fn write_u64(w: &mut impl Write, value: u64) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+/// Length-prefixed bytes
+pub fn write_bytes(w: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_u64(w, bytes.len() as u64)?;
+    w.write_all(bytes)
+}
+
+pub fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Length-prefixed bytes, refusing lengths over `max`
+pub fn read_bytes(r: &mut impl Read, max: usize) -> io::Result<Vec<u8>> {
+    let len = read_u64(r)?;
+
+    if len > max as u64 {
+        return Err(invalid(format!("Length {len} is too long")));
+    }
+
+    let mut bytes = vec![0; len as usize];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
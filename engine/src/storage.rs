@@ -0,0 +1,91 @@
+use crate::cache;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Key-value blobs saved by one module, kept as one file per key in its own
+/// directory.  File names are the hex-encoded key, so guests can use any
+/// string without reaching outside the directory.  Size limits are up to the
+/// caller.
+pub struct Storage {
+    dir: PathBuf,
+}
+
+/// Per-user data directory: `$XDG_DATA_HOME/pipe-cleaner`, or else
+/// `$HOME/.local/share/pipe-cleaner`.  `None` if neither is set to an
+/// absolute path, so saved values never end up wherever the engine happened
+/// to be launched from.
+pub fn default_dir() -> Option<PathBuf> {
+    cache::user_dir("XDG_DATA_HOME", ".local/share")
+}
+
+impl Storage {
+    pub fn new(root: &Path, module_name: &str) -> Self {
+        Self {
+            dir: root.join(module_name),
+        }
+    }
+
+    /// The stored value, or `None` if the key has never been set
+    pub fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Store the value, replacing any previous one.  It is written to a
+    /// temporary file named after this process first, so neither a crash nor
+    /// another engine saving the same key can leave half a value behind.
+    pub fn set(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let path = self.path(key);
+        let tmp = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&tmp, value)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Returns false if the key wasn't set
+    pub fn remove(&self, key: &str) -> io::Result<bool> {
+        match fs::remove_file(self.path(key)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Total bytes stored under every key except `excluding`, which is about
+    /// to be replaced
+    pub fn usage(&self, excluding: &str) -> io::Result<u64> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        let excluded = self.path(excluding);
+        let mut total = 0;
+
+        for entry in entries {
+            let entry = entry?;
+
+            if entry.path() != excluded {
+                total += entry.metadata()?.len();
+            }
+        }
+
+        Ok(total)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let name = key
+            .bytes()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        self.dir.join(name)
+    }
+}
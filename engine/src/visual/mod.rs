@@ -178,6 +178,7 @@ impl Model {
     }
 }
 
+#[derive(Default)]
 pub struct ManagerBuilder {
     meshes: Vec<ThickMesh>,
 }
@@ -193,6 +194,10 @@ impl ManagerBuilder {
         model_idx
     }
 
+    pub fn model_count(&self) -> usize {
+        self.meshes.len()
+    }
+
     pub fn build(self, max_instances: u32, device: &wgpu::Device) -> Manager {
         Manager::new(self.meshes.into(), max_instances, device)
     }
@@ -203,6 +208,7 @@ pub struct Manager {
     vertex_buffer: wgpu::Buffer,
     inst_buffer: wgpu::Buffer,
     models: Vec<Model>,
+    max_instances: u32,
 }
 
 impl Manager {
@@ -280,23 +286,29 @@ impl Manager {
             vertex_buffer,
             inst_buffer,
             models,
+            max_instances,
         }
     }
 
+    /// Write the attributes of each instance to the instance buffer, grouped
+    /// by model.  Instances past `max_instances` are dropped rather than
+    /// overflowing the buffer.
     pub fn update<'a>(
         &'_ mut self,
         queue: &'a wgpu::Queue,
         instances: impl Iterator<Item = &'a dyn Instance>,
     ) -> Vec<(Range<u32>, Range<u32>)> {
         let mut attributes = vec![Vec::<Attributes>::new(); self.models.len()];
-        for inst in instances.into_iter() {
-            attributes[inst.model()].push(inst.attributes());
+        for inst in instances.into_iter().take(self.max_instances as usize) {
+            if let Some(model_attributes) = attributes.get_mut(inst.model()) {
+                model_attributes.push(inst.attributes());
+            }
         }
 
         let mut offset = 0u64;
-        for i in 0..self.models.len() {
-            let bytes = attributes[i].as_flattened();
-            queue.write_buffer(self.instances(), offset, &*bytes);
+        for model_attributes in &attributes {
+            let bytes = model_attributes.as_flattened();
+            queue.write_buffer(self.instances(), offset, bytes);
             offset += bytes.len() as u64;
         }
 
@@ -1,6 +1,11 @@
+use crate::error::Error;
 use crate::visual;
+use crate::world::MAX_GUEST_ENTITIES;
 use std::borrow::Cow;
 
+/// Room in the instance buffer for rings, the player and bullets
+const MAX_NATIVE_INSTANCES: u32 = 3000;
+
 pub struct Camera {
     pos: [f32; 3],
     vfov: f32,
@@ -23,7 +28,7 @@ impl Camera {
         [1f32 / half_w, 1f32 / half_h, 1f32 / self.far_z]
     }
 
-    fn into_bytes(&self) -> Vec<u8> {
+    fn to_bytes(&self) -> Vec<u8> {
         self.world_to_screen()
             .into_iter()
             .flat_map(|f| f.to_ne_bytes())
@@ -57,12 +62,14 @@ impl<'a> Renderer<'a> {
     pub fn new(
         window: &sdl3::video::Window,
         vfov: f32,
+        backends: Option<wgpu::Backends>,
         mgr_builder: visual::ManagerBuilder,
-    ) -> Result<Renderer<'a>, String> {
+    ) -> Result<Renderer<'a>, Error> {
         let (width, height) = window.size();
 
-        let backends =
-            wgpu::Backends::from_env().unwrap_or_else(wgpu::Backends::all);
+        let backends = backends
+            .or_else(wgpu::Backends::from_env)
+            .unwrap_or_else(wgpu::Backends::all);
 
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
             backends,
@@ -80,9 +87,11 @@ impl<'a> Renderer<'a> {
         });
 
         let surface = unsafe {
-            let surf_targ =
-                wgpu::SurfaceTargetUnsafe::from_window(window).unwrap();
-            instance.create_surface_unsafe(surf_targ).unwrap()
+            let surf_targ = wgpu::SurfaceTargetUnsafe::from_window(window)
+                .map_err(|e| Error::WindowHandle(e.to_string()))?;
+            instance
+                .create_surface_unsafe(surf_targ)
+                .map_err(Error::Surface)?
         };
 
         let adapter_opt = pollster::block_on(instance.request_adapter(
@@ -94,7 +103,7 @@ impl<'a> Renderer<'a> {
         ));
         let adapter = match adapter_opt {
             Ok(a) => a,
-            Err(_) => return Err(String::from("No adapter found")),
+            Err(_) => return Err(Error::NoAdapter),
         };
 
         let (device, queue) = match pollster::block_on(adapter.request_device(
@@ -107,7 +116,7 @@ impl<'a> Renderer<'a> {
             },
         )) {
             Ok(a) => a,
-            Err(e) => return Err(e.to_string()),
+            Err(e) => return Err(Error::Device(e)),
         };
 
         let shader =
@@ -126,7 +135,7 @@ impl<'a> Renderer<'a> {
             pixel_height: height as f32,
         };
 
-        let cam_bytes = cam.into_bytes();
+        let cam_bytes = cam.to_bytes();
 
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("uniform_buffer"),
@@ -286,7 +295,10 @@ impl<'a> Renderer<'a> {
 
         Ok(Renderer {
             cam,
-            res_mgr: mgr_builder.build(3000, &device),
+            res_mgr: mgr_builder.build(
+                MAX_NATIVE_INSTANCES + MAX_GUEST_ENTITIES as u32,
+                &device,
+            ),
             queue,
             device,
             surface_config: surf_config,
@@ -352,7 +364,7 @@ impl<'a> Renderer<'a> {
         }
 
         self.cam.update_width_height(width as f32, height as f32);
-        let cam_bytes = self.cam.into_bytes();
+        let cam_bytes = self.cam.to_bytes();
 
         self.queue.write_buffer(&self.uniforms, 0u64, &cam_bytes);
         let ranges = self.res_mgr.update(&self.queue, instances);
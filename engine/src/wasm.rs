@@ -1,182 +1,976 @@
+use crate::cache::{self, ModuleCache};
+use crate::error::Error;
+use crate::manifest::{Capability, Manifest};
+use crate::snapshot::{
+    invalid, read_bytes, read_u32, read_u64, write_bytes, write_u32, write_u64,
+};
+use crate::storage::{self, Storage};
+use crate::visual;
 use crate::wasm_entity::{Entity, Handle};
-use crate::world::WasmWorld;
+use crate::world::{Message, ModuleId, Timer, WasmWorld};
+use bytemuck::{Pod, bytes_of_mut};
+use pipe_cleaner_shared::{
+    ABI_VERSION, EntityQuery, LogLevel, MAX_MESSAGE_SZ, MAX_STORAGE_KEY_SZ,
+    MAX_STORAGE_VALUE_SZ, MAX_TOPIC_SZ,
+};
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
 
-use wasmtime::{Caller, Engine, Extern, Linker, Module, Store, Val};
+use wasmtime::{
+    Caller, Engine, Extern, Instance, Linker, Module, Mutability, Store,
+    StoreLimits, StoreLimitsBuilder, Trap, TypedFunc, Val, WasmParams,
+    WasmResults,
+};
 
-pub struct Host {
-    world: Rc<RefCell<WasmWorld>>,
-    engine: Engine,
-    module: Module,
-    linker: Linker<Rc<RefCell<WasmWorld>>>,
+const PANIC_MESSAGE_SZ: usize = 256;
+const WASM_PAGE_SZ: usize = 64 * 1024;
+const MAX_GLOBAL_NAME_SZ: usize = 1024;
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_FUEL_PER_FRAME: u64 = 10_000_000;
+const DEFAULT_STORAGE_QUOTA: u64 = 64 * 1024;
+const DEFAULT_MAX_MEMORY: usize = 64 * 1024 * 1024;
+const DEFAULT_MAX_TABLE_ELEMENTS: usize = 10_000;
+const DEFAULT_MAX_ENTITIES: usize = 2048;
+const DEFAULT_MAX_MESSAGES_PER_TICK: usize = 256;
+
+/// Settings for a host and the module it runs
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Fuel given to the guest at the start of each frame, roughly one unit
+    /// per wasm instruction, shared by every call into it that frame.  A
+    /// guest that runs out traps with `Error::BudgetExceeded` instead of
+    /// hanging the engine.  `None` lets guests run unmetered.
+    pub fuel_per_frame: Option<u64>,
+    /// Most verbose level of guest log messages that gets printed
+    pub log_level: LogLevel,
+    /// Let the module overwrite and remove entities created by other
+    /// modules, if it asks to in its manifest.  Modules that ask without
+    /// being allowed are refused.  Any module may read any entity.
+    pub modify_foreign_entities: bool,
+    /// Directory holding each module's saved values, in a subdirectory named
+    /// after the module, or `None` to give modules no storage, so their
+    /// storage imports always fail.  Defaults to `storage::default_dir`.
+    pub data_dir: Option<PathBuf>,
+    /// Most bytes of values the module may have saved at once
+    pub storage_quota: u64,
+    /// Most bytes of linear memory the module may grow to.  Past this,
+    /// `memory.grow` fails and the guest's allocator sees it as running out
+    /// of memory.
+    pub max_memory: usize,
+    /// Most elements each of the module's tables may grow to
+    pub max_table_elements: usize,
+    /// Most entities the module may have alive at once.  Every module
+    /// together is also held to `world::MAX_GUEST_ENTITIES`.
+    pub max_entities: usize,
+    /// Most messages the module may post in one tick
+    pub max_messages_per_tick: usize,
+    /// Directory to keep compiled modules in, or `None` to compile them
+    /// every time they're loaded.  Entries are loaded without being checked,
+    /// so nobody but the current user may be able to write to it.  Defaults
+    /// to `cache::default_dir`.
+    pub cache_dir: Option<PathBuf>,
 }
 
-impl Host {
-    pub fn new<T: Into<PathBuf>>(path: T) -> Result<Self, String> {
-        let engine = Default::default();
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            fuel_per_frame: Some(DEFAULT_FUEL_PER_FRAME),
+            log_level: LogLevel::Info,
+            modify_foreign_entities: false,
+            data_dir: storage::default_dir(),
+            storage_quota: DEFAULT_STORAGE_QUOTA,
+            max_memory: DEFAULT_MAX_MEMORY,
+            max_table_elements: DEFAULT_MAX_TABLE_ELEMENTS,
+            max_entities: DEFAULT_MAX_ENTITIES,
+            max_messages_per_tick: DEFAULT_MAX_MESSAGES_PER_TICK,
+            cache_dir: cache::default_dir(),
+        }
+    }
+}
 
-        let mut file = File::open(path.into()).map_err(|e| e.to_string())?;
+/// Per-module data available to host functions
+pub struct GuestState {
+    name: String,
+    module: ModuleId,
+    world: Rc<RefCell<WasmWorld>>,
+    log_level: LogLevel,
+    modify_foreign_entities: bool,
+    storage: Option<Storage>,
+    storage_quota: u64,
+    limits: StoreLimits,
+    max_entities: usize,
+    max_messages_per_tick: usize,
+    /// Message being delivered through `PIPECLEANER_on_message`
+    message: Option<Message>,
+}
 
-        let mut bytes = Vec::new();
+impl GuestState {
+    fn new(
+        name: String,
+        module: ModuleId,
+        world: Rc<RefCell<WasmWorld>>,
+        config: &Config,
+        manifest: Option<&Manifest>,
+    ) -> Self {
+        let has = |capability| manifest.is_some_and(|m| m.has(capability));
 
-        file.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+        Self {
+            storage: config
+                .data_dir
+                .as_deref()
+                .filter(|_| has(Capability::Storage))
+                .map(|dir| Storage::new(dir, &name)),
+            name,
+            module,
+            world,
+            log_level: config.log_level,
+            modify_foreign_entities: config.modify_foreign_entities
+                && has(Capability::ForeignEntities),
+            storage_quota: config.storage_quota,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(config.max_memory)
+                .table_elements(config.max_table_elements)
+                .build(),
+            max_entities: config.max_entities,
+            max_messages_per_tick: config.max_messages_per_tick,
+            message: None,
+        }
+    }
 
-        let module = Module::new(&engine, bytes).map_err(|e| e.to_string())?;
+    /// Whether this module may overwrite or remove the entity
+    fn may_modify(&self, handle: Handle) -> bool {
+        self.modify_foreign_entities
+            || self.world.borrow().owner(handle) == Some(self.module)
+    }
+}
 
-        let mut linker = Linker::new(&engine);
+/// Panic message left by a guest in its `PIPECLEANER_panic_report` export
+#[derive(Debug, Clone)]
+pub struct PanicReport {
+    /// False if the guest panicked again while writing the message, in which
+    /// case the message may be cut short
+    pub complete: bool,
+    pub message: String,
+}
 
-        linker
-            .func_wrap("env", "PIPECLEANER_create_entity", create_entity)
-            .map_err(|e| e.to_string())?;
+/// Optional guest exports, looked up when the module is instantiated
+struct Exports {
+    instance: Instance,
+    update: Option<TypedFunc<f32, ()>>,
+    on_collision: Option<TypedFunc<(u64, u64), ()>>,
+    dispatch_think: Option<TypedFunc<(u32, u64), ()>>,
+    on_timer: Option<TypedFunc<(u64, u32), ()>>,
+    on_message: Option<TypedFunc<(u32, u32, u32), ()>>,
+}
 
-        linker
-            .func_wrap("env", "PIPECLEANER_get_entity", get_entity)
-            .map_err(|e| e.to_string())?;
+impl Exports {
+    fn new(store: &mut Store<GuestState>, instance: Instance) -> Self {
+        Self {
+            instance,
+            update: instance
+                .get_typed_func(&mut *store, "PIPECLEANER_update")
+                .ok(),
+            on_collision: instance
+                .get_typed_func(&mut *store, "PIPECLEANER_on_collision")
+                .ok(),
+            dispatch_think: instance
+                .get_typed_func(&mut *store, "PIPECLEANER_dispatch_think")
+                .ok(),
+            on_timer: instance
+                .get_typed_func(&mut *store, "PIPECLEANER_on_timer")
+                .ok(),
+            on_message: instance
+                .get_typed_func(&mut *store, "PIPECLEANER_on_message")
+                .ok(),
+        }
+    }
+}
 
-        linker
-            .func_wrap(
-                "env",
-                "PIPECLEANER_write_entity_back",
-                write_entity_back,
-            )
-            .map_err(|e| e.to_string())?;
+pub struct Host {
+    config: Config,
+    module_id: ModuleId,
+    world: Rc<RefCell<WasmWorld>>,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+    module: Module,
+    manifest: Option<Manifest>,
+    linker: Linker<GuestState>,
+    store: Store<GuestState>,
+    exports: Option<Exports>,
+}
 
-        linker
-            .func_wrap("env", "PIPECLEANER_remove_entity", remove_entity)
-            .map_err(|e| e.to_string())?;
+impl Host {
+    pub fn new<T: Into<PathBuf>>(
+        path: T,
+        world: Rc<RefCell<WasmWorld>>,
+        config: Config,
+    ) -> Result<Self, Error> {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.consume_fuel(config.fuel_per_frame.is_some());
+        let engine = Engine::new(&engine_config).map_err(Error::Engine)?;
+        let path = path.into();
+        let modified = modified_time(&path);
+        let (module, manifest) = load_module(&engine, &path, &config)?;
 
-        let world = Rc::new(RefCell::new(WasmWorld::default()));
+        let linker = link(&engine)?;
+        let module_id = world.borrow_mut().register_module();
+        let store = new_store(
+            &engine,
+            &path,
+            manifest.as_ref(),
+            module_id,
+            &world,
+            &config,
+        );
 
         Ok(Host {
+            config,
+            module_id,
             world,
-            engine,
+            path,
+            modified,
+            last_poll: Instant::now(),
             module,
+            manifest,
             linker,
+            store,
+            exports: None,
         })
     }
 
-    pub fn run(&self) -> Result<(), String> {
-        let mut store = Store::new(&self.engine, Rc::clone(&self.world));
+    /// Name of the module, taken from its manifest or else its file name
+    pub fn name(&self) -> &str {
+        &self.store.data().name
+    }
+
+    pub fn module_id(&self) -> ModuleId {
+        self.module_id
+    }
+
+    /// Give the guest a fresh fuel budget for the coming frame
+    pub fn refuel(&mut self) {
+        refuel(&mut self.store, &self.config);
+    }
+
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
 
+    /// Instantiate the module and call its `PIPECLEANER_init` export.  The
+    /// instance is kept alive afterwards so `update` can be called each tick.
+    pub fn run(&mut self) -> Result<(), Error> {
         let instance = self
             .linker
-            .instantiate(&mut store, &self.module)
-            .map_err(|e| e.to_string())?;
+            .instantiate(&mut self.store, &self.module)
+            .map_err(Error::Link)?;
 
         let init = instance
-            .get_typed_func::<(), ()>(&mut store, "PIPECLEANER_init")
-            .map_err(|e| e.to_string())?;
+            .get_typed_func::<(), ()>(&mut self.store, "PIPECLEANER_init")
+            .map_err(|_| Error::MissingExport("PIPECLEANER_init".into()))?;
+
+        self.exports = Some(Exports::new(&mut self.store, instance));
+        check_abi(&mut self.store, instance)?;
+        self.call(&init, ())?;
+        Ok(())
+    }
 
-        let panic_report_address = instance
-            .get_global(&mut store, "PIPECLEANER_panic_report")
-            .ok_or(String::from("Export not found"))?;
+    /// Call the guest's `PIPECLEANER_update` export, if it has one, with the
+    /// time step in seconds.
+    pub fn update(&mut self, dt: f32) -> Result<(), Error> {
+        if let Some(update) =
+            self.exports.as_ref().and_then(|e| e.update.clone())
+        {
+            self.call(&update, dt)?;
+        }
 
-        if let Err(e) = init.call(&mut store, ()) {
-            return Err(format!("Initialization error: {e}"));
-        } else {
-            for entity in self.world.borrow().entity_iter() {
-                println!(
-                    "Position: angle: {}, depth: {}",
-                    entity.engine_fields.position.angle,
-                    entity.engine_fields.position.depth,
-                );
+        Ok(())
+    }
+
+    /// Run the think function of each entity owned by the guest, through its
+    /// `PIPECLEANER_dispatch_think` export.  Entities removed or given a
+    /// different think by an earlier think in the same tick are skipped.
+    pub fn think(&mut self) -> Result<(), Error> {
+        let dispatch = match self
+            .exports
+            .as_ref()
+            .and_then(|e| e.dispatch_think.clone())
+        {
+            Some(dispatch) => dispatch,
+            None => return Ok(()),
+        };
+
+        let thinks = self.world.borrow().thinks(self.module_id);
+
+        for (handle, think) in thinks {
+            if self.world.borrow().think(handle) == Some(think) {
+                self.call(&dispatch, (think, handle.bits()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tell the guest about collisions involving its entities through its
+    /// `PIPECLEANER_on_collision` export.  The guest's own entity is always
+    /// passed first; if it owns both entities it is called once.
+    pub fn dispatch_collisions(
+        &mut self,
+        collisions: &[(Handle, Handle)],
+    ) -> Result<(), Error> {
+        let on_collision =
+            match self.exports.as_ref().and_then(|e| e.on_collision.clone()) {
+                Some(on_collision) => on_collision,
+                None => return Ok(()),
+            };
+
+        for &(a, b) in collisions {
+            let (owner_a, owner_b) = {
+                let world = self.world.borrow();
+                (world.owner(a), world.owner(b))
+            };
+
+            if owner_a == Some(self.module_id) {
+                self.call(&on_collision, (a.bits(), b.bits()))?;
+            } else if owner_b == Some(self.module_id) {
+                self.call(&on_collision, (b.bits(), a.bits()))?;
             }
         }
 
-        if let Val::I32(address) = panic_report_address.get(&mut store) {
-            let address = address as u32 as usize;
-            eprintln!("Panic report address: {address}");
+        Ok(())
+    }
 
-            let memory = instance.get_memory(&mut store, "memory").unwrap();
-            let mut bytes = [0u8; 4 + 4 + 256];
-            memory
-                .read(&mut store, address, &mut bytes)
-                .map_err(|e| e.to_string())?;
-
-            let code = u32::from_le_bytes(bytes[0..4].as_chunks().0[0]);
-            let msg_len =
-                u32::from_le_bytes(bytes[4..8].as_chunks().0[0]) as usize;
-
-            eprintln!("Error code: {code}");
-            eprintln!("Message length: {msg_len}");
-
-            if code == 1 || code == 2 {
-                let message_slice = &bytes[8..][..msg_len];
-
-                eprintln!(
-                    "{}",
-                    std::str::from_utf8(message_slice)
-                        .unwrap_or("Bad UTF-8 string")
-                );
-            } else {
-                eprintln!("No panic detected");
+    /// Call the guest's `PIPECLEANER_on_message` export for each message on a
+    /// topic it subscribes to, other than its own, with the message's kind,
+    /// topic length and data length.  The guest copies the message out with
+    /// `PIPECLEANER_read_message` during the call.
+    pub fn dispatch_messages(
+        &mut self,
+        messages: &[Message],
+    ) -> Result<(), Error> {
+        let on_message =
+            match self.exports.as_ref().and_then(|e| e.on_message.clone()) {
+                Some(on_message) => on_message,
+                None => return Ok(()),
+            };
+
+        for message in messages {
+            if message.sender == self.module_id
+                || !self
+                    .world
+                    .borrow()
+                    .is_subscribed(self.module_id, &message.topic)
+            {
+                continue;
             }
-        } else {
-            eprintln!("Failed to find panic report export");
+
+            let params = (
+                message.kind,
+                message.topic.len() as u32,
+                message.data.len() as u32,
+            );
+
+            self.store.data_mut().message = Some(message.clone());
+            let res = self.call(&on_message, params);
+            self.store.data_mut().message = None;
+            res?;
+        }
+
+        Ok(())
+    }
+
+    /// Call the guest's `PIPECLEANER_on_timer` export for each due timer it
+    /// scheduled, with the timer's entity (0 if none) and callback id
+    pub fn dispatch_timers(&mut self, timers: &[Timer]) -> Result<(), Error> {
+        let on_timer =
+            match self.exports.as_ref().and_then(|e| e.on_timer.clone()) {
+                Some(on_timer) => on_timer,
+                None => return Ok(()),
+            };
+
+        for timer in timers {
+            if timer.module == self.module_id {
+                let handle = timer.handle.map_or(0, |h| h.bits());
+                self.call(&on_timer, (handle, timer.callback))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the module file has changed since it was loaded and, if
+    /// so, recompile it and swap in a fresh instance.  Entities in the wasm
+    /// world are left untouched; the new instance's optional
+    /// `PIPECLEANER_reload` export is called instead of `PIPECLEANER_init`
+    /// so the guest can find its handles again.
+    ///
+    /// Returns true if the module was reloaded.  On failure the previous
+    /// instance stays in place.
+    pub fn poll_reload(&mut self) -> Result<bool, Error> {
+        if self.exports.is_none()
+            || self.last_poll.elapsed() < RELOAD_POLL_INTERVAL
+        {
+            return Ok(false);
+        }
+
+        self.last_poll = Instant::now();
+        let modified = modified_time(&self.path);
+
+        if modified.is_none() || modified == self.modified {
+            return Ok(false);
+        }
+
+        // Only try each version of the file once, even if it fails to load
+        self.modified = modified;
+
+        let (module, manifest) =
+            load_module(self.store.engine(), &self.path, &self.config)?;
+
+        let mut store = new_store(
+            self.store.engine(),
+            &self.path,
+            manifest.as_ref(),
+            self.module_id,
+            &self.world,
+            &self.config,
+        );
+
+        let instance = self
+            .linker
+            .instantiate(&mut store, &module)
+            .map_err(Error::Link)?;
+
+        check_abi(&mut store, instance)?;
+
+        // Think functions are indices into the old instance's function table,
+        // so the guest has to set them again from `PIPECLEANER_reload`
+        let thinks = self.world.borrow_mut().take_thinks(self.module_id);
+
+        if let Ok(reload) =
+            instance.get_typed_func::<(), ()>(&mut store, "PIPECLEANER_reload")
+            && let Err(e) = call_guest(&mut store, instance, &reload, ())
+        {
+            let mut world = self.world.borrow_mut();
+
+            for (handle, think) in thinks {
+                world.set_think(handle, Some(think));
+            }
+
+            return Err(e);
+        }
+
+        self.exports = Some(Exports::new(&mut store, instance));
+        self.module = module;
+        self.manifest = manifest;
+        self.store = store;
+
+        Ok(true)
+    }
+
+    /// Write the module's name, linear memory and exported mutable globals.
+    /// Only valid between calls into the guest.
+    pub fn snapshot(&mut self, w: &mut impl Write) -> Result<(), Error> {
+        let instance = self.instance();
+
+        let memory = instance
+            .get_memory(&mut self.store, "memory")
+            .ok_or_else(|| Error::MissingExport("memory".into()))?;
+
+        let globals = instance
+            .exports(&mut self.store)
+            .filter_map(|export| {
+                let name = export.name().to_owned();
+                export.into_global().map(|global| (name, global))
+            })
+            .collect::<Vec<_>>();
+
+        let mut values = Vec::new();
+
+        for (name, global) in globals {
+            if global.ty(&self.store).mutability() != Mutability::Var {
+                continue;
+            }
+
+            let (tag, bits) = match global.get(&mut self.store) {
+                Val::I32(value) => (GlobalTag::I32, value as u32 as u64),
+                Val::I64(value) => (GlobalTag::I64, value as u64),
+                Val::F32(bits) => (GlobalTag::F32, bits as u64),
+                Val::F64(bits) => (GlobalTag::F64, bits),
+                _ => continue,
+            };
+
+            values.push((name, tag, bits));
+        }
+
+        (|| {
+            write_bytes(w, self.name().as_bytes())?;
+            write_bytes(w, memory.data(&self.store))?;
+            write_u32(w, values.len() as u32)?;
+
+            for (name, tag, bits) in &values {
+                write_bytes(w, name.as_bytes())?;
+                write_u32(w, *tag as u32)?;
+                write_u64(w, *bits)?;
+            }
+
+            Ok(())
+        })()
+        .map_err(Error::Snapshot)
+    }
+
+    /// Restore state written by `snapshot` from an instance of the same
+    /// module
+    pub fn restore(&mut self, r: &mut impl Read) -> Result<(), Error> {
+        let instance = self.instance();
+        let name =
+            read_bytes(r, MAX_GLOBAL_NAME_SZ).map_err(Error::Snapshot)?;
+
+        if name != self.name().as_bytes() {
+            return Err(Error::Snapshot(invalid(format!(
+                "Expected module {}, found {}",
+                self.name(),
+                String::from_utf8_lossy(&name)
+            ))));
+        }
+
+        let memory = instance
+            .get_memory(&mut self.store, "memory")
+            .ok_or_else(|| Error::MissingExport("memory".into()))?;
+
+        let bytes =
+            read_bytes(r, self.config.max_memory).map_err(Error::Snapshot)?;
+        let size = memory.data_size(&self.store);
+
+        if bytes.len() > size {
+            let pages = (bytes.len() - size).div_ceil(WASM_PAGE_SZ) as u64;
+
+            memory
+                .grow(&mut self.store, pages)
+                .map_err(|e| Error::Snapshot(invalid(e.to_string())))?;
+        }
+
+        let data = memory.data_mut(&mut self.store);
+        data[..bytes.len()].copy_from_slice(&bytes);
+        data[bytes.len()..].fill(0);
+
+        let global_ct = read_u32(r).map_err(Error::Snapshot)?;
+
+        for _ in 0..global_ct {
+            let name =
+                read_bytes(r, MAX_GLOBAL_NAME_SZ).map_err(Error::Snapshot)?;
+            let tag = read_u32(r).map_err(Error::Snapshot)?;
+            let bits = read_u64(r).map_err(Error::Snapshot)?;
+            let name = String::from_utf8_lossy(&name);
+
+            let value = match GlobalTag::from_u32(tag) {
+                Some(GlobalTag::I32) => Val::I32(bits as u32 as i32),
+                Some(GlobalTag::I64) => Val::I64(bits as i64),
+                Some(GlobalTag::F32) => Val::F32(bits as u32),
+                Some(GlobalTag::F64) => Val::F64(bits),
+                None => {
+                    return Err(Error::Snapshot(invalid(format!(
+                        "Bad type for global {name}"
+                    ))));
+                }
+            };
+
+            let global =
+                instance.get_global(&mut self.store, &name).ok_or_else(
+                    || Error::Snapshot(invalid(format!("No global {name}"))),
+                )?;
+
+            global
+                .set(&mut self.store, value)
+                .map_err(|e| Error::Snapshot(invalid(e.to_string())))?;
+        }
+
+        Ok(())
+    }
+
+    fn instance(&self) -> Instance {
+        self.exports
+            .as_ref()
+            .expect("Module should be instantiated before it is used")
+            .instance
+    }
+
+    /// Call a function exported by the current instance
+    fn call<P: WasmParams, R: WasmResults>(
+        &mut self,
+        func: &TypedFunc<P, R>,
+        params: P,
+    ) -> Result<R, Error> {
+        let instance = self.instance();
+        call_guest(&mut self.store, instance, func, params)
+    }
+}
+
+/// Type of a global in a snapshot
+#[derive(Clone, Copy)]
+enum GlobalTag {
+    I32 = 0,
+    I64 = 1,
+    F32 = 2,
+    F64 = 3,
+}
+
+impl GlobalTag {
+    fn from_u32(tag: u32) -> Option<Self> {
+        match tag {
+            0 => Some(GlobalTag::I32),
+            1 => Some(GlobalTag::I64),
+            2 => Some(GlobalTag::F32),
+            3 => Some(GlobalTag::F64),
+            _ => None,
         }
+    }
+}
+
+/// Call a guest function out of what is left of the frame's fuel budget
+fn call_guest<P: WasmParams, R: WasmResults>(
+    store: &mut Store<GuestState>,
+    instance: Instance,
+    func: &TypedFunc<P, R>,
+    params: P,
+) -> Result<R, Error> {
+    func.call(&mut *store, params)
+        .map_err(|e| guest_error(store, instance, e))
+}
+
+/// Top up the store's fuel to the configured per-frame budget
+pub fn refuel<T: 'static>(store: &mut Store<T>, config: &Config) {
+    if let Some(fuel) = config.fuel_per_frame {
+        store
+            .set_fuel(fuel)
+            .expect("Engine should be configured to consume fuel");
+    }
+}
+
+/// Linker providing every import of the raw ABI
+fn link(engine: &Engine) -> Result<Linker<GuestState>, Error> {
+    let mut linker = Linker::new(engine);
+
+    linker
+        .func_wrap("env", "PIPECLEANER_create_entity", create_entity)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_get_entity", get_entity)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_write_entity_back", write_entity_back)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_remove_entity", remove_entity)
+        .map_err(Error::Link)?;
 
+    linker
+        .func_wrap("env", "PIPECLEANER_log", log)
+        .map_err(Error::Link)?;
 
+    linker
+        .func_wrap("env", "PIPECLEANER_input_state", input_state)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_query_entities", query_entities)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_register_model", register_model)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_set_think", set_think)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_random", random)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_schedule", schedule)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_cancel_timer", cancel_timer)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_storage_get", storage_get)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_storage_set", storage_set)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_storage_remove", storage_remove)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_subscribe", subscribe)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_unsubscribe", unsubscribe)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_post", post)
+        .map_err(Error::Link)?;
+
+    linker
+        .func_wrap("env", "PIPECLEANER_read_message", read_message)
+        .map_err(Error::Link)?;
+
+    Ok(linker)
+}
+
+/// Refuse modules built against a different version of the engine interface
+fn check_abi(
+    store: &mut Store<GuestState>,
+    instance: Instance,
+) -> Result<(), Error> {
+    let abi_version = instance
+        .get_typed_func::<(), u32>(&mut *store, "PIPECLEANER_abi_version")
+        .map_err(|_| Error::AbiMismatch {
+            expected: ABI_VERSION,
+            found: None,
+        })?;
+
+    let found = call_guest(store, instance, &abi_version, ())?;
+
+    if found == ABI_VERSION {
         Ok(())
+    } else {
+        Err(Error::AbiMismatch {
+            expected: ABI_VERSION,
+            found: Some(found),
+        })
     }
 }
 
-fn create_entity(caller: Caller<'_, Rc<RefCell<WasmWorld>>>) -> u64 {
-    caller.data().borrow_mut().create_entity().bits()
+/// Store for one instance of a module, limited to the resources the config
+/// allows
+fn new_store(
+    engine: &Engine,
+    path: &Path,
+    manifest: Option<&Manifest>,
+    module: ModuleId,
+    world: &Rc<RefCell<WasmWorld>>,
+    config: &Config,
+) -> Store<GuestState> {
+    let name = match manifest {
+        Some(manifest) => manifest.name.clone(),
+        None => file_name(path),
+    };
+
+    let state =
+        GuestState::new(name, module, Rc::clone(world), config, manifest);
+    let mut store = Store::new(engine, state);
+    store.limiter(|guest| &mut guest.limits);
+    refuel(&mut store, config);
+    store
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
 }
 
+/// Manifest of the module at `path`, without compiling it
+pub fn read_manifest(path: &Path) -> Result<Option<Manifest>, Error> {
+    Manifest::from_module(&read_module(path)?)
+}
+
+/// Name the module at `path` loads under, taken from its manifest or else its
+/// file name
+pub fn module_name(path: &Path) -> Result<String, Error> {
+    Ok(match read_manifest(path)? {
+        Some(manifest) => manifest.name,
+        None => file_name(path),
+    })
+}
+
+/// Name of a module without a manifest
+pub fn file_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Compile the module at `path`, refusing it if its manifest is invalid or
+/// asks for more than the config allows
+fn load_module(
+    engine: &Engine,
+    path: &Path,
+    config: &Config,
+) -> Result<(Module, Option<Manifest>), Error> {
+    let bytes = read_module(path)?;
+    let manifest = Manifest::from_module(&bytes)?;
+
+    if let Some(manifest) = &manifest {
+        manifest.validate()?;
+
+        if manifest.has(Capability::ForeignEntities)
+            && !config.modify_foreign_entities
+        {
+            return Err(Error::CapabilityDenied(Capability::ForeignEntities));
+        }
+    }
+
+    // Only compile, and so cache, modules that passed the checks above
+    let module = match &config.cache_dir {
+        Some(dir) => ModuleCache::new(dir).load(engine, path, &bytes)?,
+        None => Module::new(engine, &bytes).map_err(Error::Compile)?,
+    };
+
+    Ok((module, manifest))
+}
+
+pub fn read_module(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            Error::ModuleNotFound(path.to_owned())
+        } else {
+            Error::ModuleRead(path.to_owned(), e)
+        }
+    })?;
+
+    let mut bytes = Vec::new();
+
+    file.read_to_end(&mut bytes)
+        .map_err(|e| Error::ModuleRead(path.to_owned(), e))?;
+
+    Ok(bytes)
+}
+
+/// Classify an error returned from a guest call, attaching the guest's panic
+/// report if it left one
+fn guest_error<T: 'static>(
+    store: &mut Store<T>,
+    instance: Instance,
+    error: wasmtime::Error,
+) -> Error {
+    if error.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+        return Error::BudgetExceeded;
+    }
+
+    match panic_report(store, instance) {
+        Some(report) => Error::GuestPanic(report),
+        None => Error::Trap(error),
+    }
+}
+
+fn panic_report<T: 'static>(
+    store: &mut Store<T>,
+    instance: Instance,
+) -> Option<PanicReport> {
+    let panic_report_address =
+        instance.get_global(&mut *store, "PIPECLEANER_panic_report")?;
+
+    let address = match panic_report_address.get(&mut *store) {
+        Val::I32(address) => address as u32 as usize,
+        _ => return None,
+    };
+
+    let memory = instance.get_memory(&mut *store, "memory")?;
+    let mut bytes = [0u8; 4 + 4 + PANIC_MESSAGE_SZ];
+    memory.read(&mut *store, address, &mut bytes).ok()?;
+
+    let code = u32::from_le_bytes(bytes[0..4].as_chunks().0[0]);
+    let msg_len = u32::from_le_bytes(bytes[4..8].as_chunks().0[0]) as usize;
+    let message_slice = &bytes[8..][..msg_len.min(PANIC_MESSAGE_SZ)];
+    let message = String::from_utf8_lossy(message_slice).into_owned();
+
+    match code {
+        1 => Some(PanicReport {
+            complete: true,
+            message,
+        }),
+        2 => Some(PanicReport {
+            complete: false,
+            message,
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the new entity's handle, or 0 if the module is at its entity
+/// limit or the world is out of handles
+fn create_entity(caller: Caller<'_, GuestState>) -> u64 {
+    let guest = caller.data();
+    let mut world = guest.world.borrow_mut();
+
+    if world.owned_count(guest.module) >= guest.max_entities {
+        return 0;
+    }
+
+    world
+        .create_entity(guest.module)
+        .map_or(0, |handle| handle.bits())
+}
+
+/// Copy an entity to `address`, which must be 4-byte aligned
 fn get_entity(
-    mut caller: Caller<'_, Rc<RefCell<WasmWorld>>>,
+    mut caller: Caller<'_, GuestState>,
     handle_bits: u64,
     address: u32,
 ) -> u32 {
-    let address = address as usize;
-
-    if let Some(handle) = Handle::from_bits(handle_bits)
-        && {
-            let world = Rc::clone(caller.data());
-
-            let memory = match caller.get_export("memory").unwrap() {
-                Extern::Memory(m) => m.data_mut(&mut caller),
-                _ => panic!("Expected export to be memory"),
-            };
+    let (mut memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
 
-            world.borrow().write_entity_to_guest(
-                handle,
-                &mut memory[address..address + size_of::<Entity>()],
-            )
-        }
+    let entity = match Handle::from_bits(handle_bits)
+        .and_then(|handle| guest.world.borrow().entity(handle))
     {
-        0
-    } else {
-        1
+        Some(entity) => entity,
+        None => return 1,
+    };
+
+    match memory.write(address, &le_engine_fields(entity)) {
+        Some(()) => 0,
+        None => 1,
     }
 }
 
+/// Overwrite an entity with the copy at `address`, which must be 4-byte
+/// aligned
 fn write_entity_back(
-    mut caller: Caller<'_, Rc<RefCell<WasmWorld>>>,
+    mut caller: Caller<'_, GuestState>,
     handle_bits: u64,
     address: u32,
 ) -> u32 {
-    let address = address as usize;
+    let (memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
 
     if let Some(handle) = Handle::from_bits(handle_bits)
-        && {
-            let world = Rc::clone(caller.data());
-
-            let memory = match caller.get_export("memory").unwrap() {
-                Extern::Memory(m) => m.data(&caller),
-                _ => panic!("Expected export to be memory"),
-            };
+        && guest.may_modify(handle)
+        && let Some(entity) = memory.read::<Entity>(address)
+        && guest
+            .world
+            .borrow_mut()
+            .set_entity(handle, le_engine_fields(entity))
+    {
+        0
+    } else {
+        1
+    }
+}
 
-            world.borrow_mut().read_entity_from_guest(
-                handle,
-                &memory[address..address + size_of::<Entity>()],
-            )
-        }
+fn remove_entity(caller: Caller<'_, GuestState>, handle_bits: u64) -> u32 {
+    if let Some(handle) = Handle::from_bits(handle_bits)
+        && caller.data().may_modify(handle)
+        && caller.data().world.borrow_mut().remove_entity(handle)
     {
         0
     } else {
@@ -184,15 +978,920 @@ fn write_entity_back(
     }
 }
 
-fn remove_entity(
-    caller: Caller<'_, Rc<RefCell<WasmWorld>>>,
+/// Set the think function of an entity the caller owns.  `think` is an index
+/// into the caller's function table, passed back to its
+/// `PIPECLEANER_dispatch_think` export each tick; 0 clears it.
+fn set_think(
+    caller: Caller<'_, GuestState>,
     handle_bits: u64,
+    think: u32,
 ) -> u32 {
+    let guest = caller.data();
+
     if let Some(handle) = Handle::from_bits(handle_bits)
-        && caller.data().borrow_mut().remove_entity(handle)
+        && guest.world.borrow().owner(handle) == Some(guest.module)
+        && guest
+            .world
+            .borrow_mut()
+            .set_think(handle, (think != 0).then_some(think))
     {
         0
     } else {
         1
     }
 }
+
+/// Next number from the world's seeded generator
+fn random(caller: Caller<'_, GuestState>) -> u64 {
+    caller.data().world.borrow_mut().random()
+}
+
+/// Schedule a call to the caller's `PIPECLEANER_on_timer` export `delay`
+/// seconds from now, tied to an entity unless the handle is 0.  Returns the
+/// timer's id, or 0 on failure.
+fn schedule(
+    caller: Caller<'_, GuestState>,
+    handle_bits: u64,
+    delay: f32,
+    callback: u32,
+) -> u64 {
+    let guest = caller.data();
+
+    let handle = match handle_bits {
+        0 => None,
+        bits => match Handle::from_bits(bits) {
+            Some(handle) => Some(handle),
+            None => return 0,
+        },
+    };
+
+    guest
+        .world
+        .borrow_mut()
+        .schedule(guest.module, handle, delay, callback)
+        .unwrap_or(0)
+}
+
+fn cancel_timer(caller: Caller<'_, GuestState>, id: u64) -> u32 {
+    let guest = caller.data();
+
+    if guest.world.borrow_mut().cancel_timer(guest.module, id) {
+        0
+    } else {
+        1
+    }
+}
+
+/// Copy the value saved under the key at `key_ptr` into the buffer at
+/// `buf_ptr`, writing its full length to `len_ptr`.  Fails if the key isn't
+/// set or the value doesn't fit in `buf_cap` bytes, though the length is
+/// still written in the latter case.
+fn storage_get(
+    mut caller: Caller<'_, GuestState>,
+    key_ptr: u32,
+    key_len: u32,
+    buf_ptr: u32,
+    buf_cap: u32,
+    len_ptr: u32,
+) -> u32 {
+    let (mut memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    let storage = match &guest.storage {
+        Some(storage) => storage,
+        None => return 1,
+    };
+
+    let value = match memory
+        .str(key_ptr, key_len, MAX_STORAGE_KEY_SZ)
+        .map(|key| storage.get(key))
+    {
+        Some(Ok(Some(value))) => value,
+        Some(Err(e)) => {
+            eprintln!("[{}] Storage: {e}", guest.name);
+            return 1;
+        }
+        _ => return 1,
+    };
+
+    // The whole buffer has to be in range, not just the part being written
+    if memory.bytes(buf_ptr, buf_cap).is_none() {
+        return 1;
+    }
+
+    if memory
+        .write(len_ptr, &(value.len() as u32).to_le())
+        .is_none()
+    {
+        return 1;
+    }
+
+    if value.len() > buf_cap as usize {
+        return 1;
+    }
+
+    match memory.bytes_mut(buf_ptr, value.len() as u32) {
+        Some(bytes) => {
+            bytes.copy_from_slice(&value);
+            0
+        }
+        None => 1,
+    }
+}
+
+/// Save the value at `val_ptr` under the key at `key_ptr`, as long as it stays
+/// within `MAX_STORAGE_VALUE_SZ` and the module's storage quota
+fn storage_set(
+    mut caller: Caller<'_, GuestState>,
+    key_ptr: u32,
+    key_len: u32,
+    val_ptr: u32,
+    val_len: u32,
+) -> u32 {
+    let (memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    let (key, value) = match (
+        memory.str(key_ptr, key_len, MAX_STORAGE_KEY_SZ),
+        memory.bytes(val_ptr, val_len),
+    ) {
+        (Some(key), Some(value)) if value.len() <= MAX_STORAGE_VALUE_SZ => {
+            (key, value)
+        }
+        _ => return 1,
+    };
+
+    let storage = match &guest.storage {
+        Some(storage) => storage,
+        None => return 1,
+    };
+
+    let res = storage.usage(key).and_then(|usage| {
+        if usage + value.len() as u64 > guest.storage_quota {
+            Ok(false)
+        } else {
+            storage.set(key, value).map(|()| true)
+        }
+    });
+
+    match res {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("[{}] Storage: {e}", guest.name);
+            1
+        }
+    }
+}
+
+fn storage_remove(
+    mut caller: Caller<'_, GuestState>,
+    key_ptr: u32,
+    key_len: u32,
+) -> u32 {
+    let (memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    let storage = match &guest.storage {
+        Some(storage) => storage,
+        None => return 1,
+    };
+
+    match memory
+        .str(key_ptr, key_len, MAX_STORAGE_KEY_SZ)
+        .map(|key| storage.remove(key))
+    {
+        Some(Ok(true)) => 0,
+        Some(Err(e)) => {
+            eprintln!("[{}] Storage: {e}", guest.name);
+            1
+        }
+        _ => 1,
+    }
+}
+
+/// Receive messages posted to the topic at `topic_ptr` by other modules
+fn subscribe(
+    mut caller: Caller<'_, GuestState>,
+    topic_ptr: u32,
+    topic_len: u32,
+) -> u32 {
+    let (memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    match memory.str(topic_ptr, topic_len, MAX_TOPIC_SZ) {
+        Some(topic) => {
+            guest.world.borrow_mut().subscribe(guest.module, topic);
+            0
+        }
+        None => 1,
+    }
+}
+
+fn unsubscribe(
+    mut caller: Caller<'_, GuestState>,
+    topic_ptr: u32,
+    topic_len: u32,
+) -> u32 {
+    let (memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    match memory.str(topic_ptr, topic_len, MAX_TOPIC_SZ) {
+        Some(topic)
+            if guest.world.borrow_mut().unsubscribe(guest.module, topic) =>
+        {
+            0
+        }
+        _ => 1,
+    }
+}
+
+/// Post `data_len` bytes at `data_ptr` to the topic at `topic_ptr`, tagged
+/// with `kind`.  Subscribers receive it on the next tick.
+fn post(
+    mut caller: Caller<'_, GuestState>,
+    topic_ptr: u32,
+    topic_len: u32,
+    kind: u32,
+    data_ptr: u32,
+    data_len: u32,
+) -> u32 {
+    let (memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    let (topic, data) = match (
+        memory.str(topic_ptr, topic_len, MAX_TOPIC_SZ),
+        memory.bytes(data_ptr, data_len),
+    ) {
+        (Some(topic), Some(data)) if data.len() <= MAX_MESSAGE_SZ => {
+            (topic, data)
+        }
+        _ => return 1,
+    };
+
+    let mut world = guest.world.borrow_mut();
+
+    if world.pending_count(guest.module) >= guest.max_messages_per_tick {
+        return 1;
+    }
+
+    world.post(Message {
+        sender: guest.module,
+        topic: topic.to_owned(),
+        kind,
+        data: data.to_vec(),
+    });
+
+    0
+}
+
+/// Copy the topic and data of the message being delivered to `topic_ptr` and
+/// `data_ptr`, which must have room for the lengths passed to
+/// `PIPECLEANER_on_message`.  Fails outside of `PIPECLEANER_on_message`.
+fn read_message(
+    mut caller: Caller<'_, GuestState>,
+    topic_ptr: u32,
+    data_ptr: u32,
+) -> u32 {
+    let (mut memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    let message = match &guest.message {
+        Some(message) => message,
+        None => return 1,
+    };
+
+    let topic = message.topic.as_bytes();
+
+    match memory.bytes_mut(topic_ptr, topic.len() as u32) {
+        Some(bytes) => bytes.copy_from_slice(topic),
+        None => return 1,
+    }
+
+    match memory.bytes_mut(data_ptr, message.data.len() as u32) {
+        Some(bytes) => {
+            bytes.copy_from_slice(&message.data);
+            0
+        }
+        None => 1,
+    }
+}
+
+/// Checked access to a guest's linear memory.  Imports go through this
+/// rather than slicing memory themselves, so that a bad pointer from the
+/// guest makes the import fail instead of panicking the host.
+struct GuestMemory<'a> {
+    bytes: &'a mut [u8],
+}
+
+impl<'a> GuestMemory<'a> {
+    /// Memory of the calling guest along with its state, or `None` if it
+    /// doesn't export its memory
+    fn of(
+        caller: &'a mut Caller<'_, GuestState>,
+    ) -> Option<(Self, &'a mut GuestState)> {
+        let memory = match caller.get_export("memory") {
+            Some(Extern::Memory(m)) => m,
+            _ => return None,
+        };
+
+        let (bytes, guest) = memory.data_and_store_mut(caller);
+        Some((Self { bytes }, guest))
+    }
+
+    /// Bytes `ptr..ptr + len`, or `None` if any of them are out of bounds
+    fn bytes(&self, ptr: u32, len: u32) -> Option<&[u8]> {
+        let start = ptr as usize;
+        let end = start.checked_add(len as usize)?;
+        self.bytes.get(start..end)
+    }
+
+    /// Mutable bytes `ptr..ptr + len`, or `None` if any of them are out of
+    /// bounds
+    fn bytes_mut(&mut self, ptr: u32, len: u32) -> Option<&mut [u8]> {
+        let start = ptr as usize;
+        let end = start.checked_add(len as usize)?;
+        self.bytes.get_mut(start..end)
+    }
+
+    /// String at `ptr`, if it is valid UTF-8 and between 1 and `max_len`
+    /// bytes long
+    fn str(&self, ptr: u32, len: u32, max_len: usize) -> Option<&str> {
+        if len == 0 || len as usize > max_len {
+            return None;
+        }
+
+        std::str::from_utf8(self.bytes(ptr, len)?).ok()
+    }
+
+    /// Copy of the value at `ptr`, or `None` if it is out of bounds or `ptr`
+    /// isn't aligned for `T`.  Byte order is left as the guest wrote it.
+    fn read<T: Pod>(&self, ptr: u32) -> Option<T> {
+        let bytes = self.bytes(aligned::<T>(ptr)?, size_of::<T>() as u32)?;
+        Some(bytemuck::pod_read_unaligned(bytes))
+    }
+
+    /// Copy `value` to `ptr`, or `None` if it would be out of bounds or
+    /// `ptr` isn't aligned for `T`.  Byte order is left as it is.
+    fn write<T: Pod>(&mut self, ptr: u32, value: &T) -> Option<()> {
+        let len = size_of::<T>() as u32;
+        let bytes = self.bytes_mut(aligned::<T>(ptr)?, len)?;
+        bytes.copy_from_slice(bytemuck::bytes_of(value));
+        Some(())
+    }
+}
+
+/// `ptr`, if it is aligned for `T`
+fn aligned<T>(ptr: u32) -> Option<u32> {
+    (ptr as usize)
+        .is_multiple_of(align_of::<T>())
+        .then_some(ptr)
+}
+
+/// Convert every 32-bit field of a shared struct between native and
+/// little-endian (guest) byte order
+fn le_fields<T: Pod>(mut value: T) -> T {
+    for field in bytemuck::cast_slice_mut::<_, u32>(bytes_of_mut(&mut value)) {
+        *field = u32::from_le(*field);
+    }
+
+    value
+}
+
+/// Convert an entity's engine fields between native and little-endian byte
+/// order.  Game fields are the guest's business.
+fn le_engine_fields(mut entity: Entity) -> Entity {
+    entity.engine_fields = le_fields(entity.engine_fields);
+    entity
+}
+
+fn log(
+    mut caller: Caller<'_, GuestState>,
+    level: u32,
+    ptr: u32,
+    len: u32,
+) -> u32 {
+    let level = match LogLevel::from_u32(level) {
+        Some(level) => level,
+        None => return 1,
+    };
+
+    let (memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    let bytes = match memory.bytes(ptr, len) {
+        Some(bytes) => bytes,
+        None => return 1,
+    };
+
+    if level <= guest.log_level {
+        let message = String::from_utf8_lossy(bytes);
+
+        if level <= LogLevel::Warn {
+            eprintln!("[{}] {level}: {message}", guest.name);
+        } else {
+            println!("[{}] {level}: {message}", guest.name);
+        }
+    }
+
+    0
+}
+
+fn input_state(mut caller: Caller<'_, GuestState>, ptr: u32) -> u32 {
+    let (mut memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    let state = le_fields(guest.world.borrow().input());
+
+    match memory.write(ptr, &state) {
+        Some(()) => 0,
+        None => 1,
+    }
+}
+
+/// Write the handles of entities matching the query at `query_ptr` to the
+/// array of `out_cap` handles at `out_ptr`, and the total number of matches
+/// (which may exceed `out_cap`) to `count_ptr`
+fn query_entities(
+    mut caller: Caller<'_, GuestState>,
+    query_ptr: u32,
+    out_ptr: u32,
+    out_cap: u32,
+    count_ptr: u32,
+) -> u32 {
+    let (mut memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    let query = match memory.read::<EntityQuery>(query_ptr) {
+        Some(query) => le_fields(query),
+        None => return 1,
+    };
+
+    let out_len = match out_cap.checked_mul(size_of::<u64>() as u32) {
+        Some(len) => len,
+        None => return 1,
+    };
+
+    let out = match memory.bytes_mut(out_ptr, out_len) {
+        Some(out) => out,
+        None => return 1,
+    };
+
+    let world = guest.world.borrow();
+    let mut slots = out.chunks_exact_mut(size_of::<u64>());
+    let mut count = 0u32;
+
+    for handle in world.query(&query, guest.module) {
+        if let Some(slot) = slots.next() {
+            slot.copy_from_slice(&handle.bits().to_le_bytes());
+        }
+
+        count += 1;
+    }
+
+    match memory.write(count_ptr, &count.to_le()) {
+        Some(()) => 0,
+        None => 1,
+    }
+}
+
+/// Register a mesh of `vertex_ct` points at `vertices_ptr` joined by the line
+/// segments listed in the `index_ct` indices at `indices_ptr`, and write its
+/// model id to `model_ptr`.  Only possible during `PIPECLEANER_init`.
+fn register_model(
+    mut caller: Caller<'_, GuestState>,
+    vertices_ptr: u32,
+    vertex_ct: u32,
+    indices_ptr: u32,
+    index_ct: u32,
+    model_ptr: u32,
+) -> u32 {
+    let (mut memory, guest) = match GuestMemory::of(&mut caller) {
+        Some(memory) => memory,
+        None => return 1,
+    };
+
+    // Check the model id can be written before registering anything, so a
+    // bad pointer doesn't leave a model behind
+    if memory.read::<u32>(model_ptr).is_none() {
+        return 1;
+    }
+
+    let vertices_len = match vertex_ct.checked_mul(size_of::<[f32; 3]>() as u32)
+    {
+        Some(len) => len,
+        None => return 1,
+    };
+
+    let indices_len = match index_ct.checked_mul(size_of::<u32>() as u32) {
+        Some(len) => len,
+        None => return 1,
+    };
+
+    let coords = match memory.bytes(vertices_ptr, vertices_len) {
+        Some(bytes) => bytes
+            .as_chunks()
+            .0
+            .iter()
+            .map(|&coord| f32::from_le_bytes(coord))
+            .collect::<Vec<_>>(),
+        None => return 1,
+    };
+
+    let vertices = coords.as_chunks::<3>().0.into();
+
+    let indices = match memory.bytes(indices_ptr, indices_len) {
+        Some(bytes) => bytes
+            .as_chunks()
+            .0
+            .iter()
+            .map(|&idx| u32::from_le_bytes(idx))
+            .collect(),
+        None => return 1,
+    };
+
+    let mesh = visual::BaseMesh { vertices, indices };
+
+    let model = match guest.world.borrow_mut().register_model(mesh) {
+        Some(model) => model,
+        None => return 1,
+    };
+
+    match memory.write(model_ptr, &model.to_le()) {
+        Some(()) => 0,
+        None => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::WasmParams;
+
+    /// Re-exports every import that takes a pointer through a wrapper, so
+    /// that the import sees this module as its caller
+    const IMPORTS_WAT: &str = r#"
+        (module
+          (import "env" "PIPECLEANER_get_entity"
+            (func $get_entity (param i64 i32) (result i32)))
+          (import "env" "PIPECLEANER_write_entity_back"
+            (func $write_entity_back (param i64 i32) (result i32)))
+          (import "env" "PIPECLEANER_log"
+            (func $log (param i32 i32 i32) (result i32)))
+          (import "env" "PIPECLEANER_input_state"
+            (func $input_state (param i32) (result i32)))
+          (import "env" "PIPECLEANER_query_entities"
+            (func $query_entities (param i32 i32 i32 i32) (result i32)))
+          (import "env" "PIPECLEANER_register_model"
+            (func $register_model (param i32 i32 i32 i32 i32) (result i32)))
+          (import "env" "PIPECLEANER_storage_get"
+            (func $storage_get (param i32 i32 i32 i32 i32) (result i32)))
+          (import "env" "PIPECLEANER_storage_set"
+            (func $storage_set (param i32 i32 i32 i32) (result i32)))
+          (import "env" "PIPECLEANER_storage_remove"
+            (func $storage_remove (param i32 i32) (result i32)))
+          (import "env" "PIPECLEANER_subscribe"
+            (func $subscribe (param i32 i32) (result i32)))
+          (import "env" "PIPECLEANER_unsubscribe"
+            (func $unsubscribe (param i32 i32) (result i32)))
+          (import "env" "PIPECLEANER_post"
+            (func $post (param i32 i32 i32 i32 i32) (result i32)))
+          (import "env" "PIPECLEANER_read_message"
+            (func $read_message (param i32 i32) (result i32)))
+
+          (memory (export "memory") 1)
+
+          (func (export "get_entity") (param i64 i32) (result i32)
+            (call $get_entity (local.get 0) (local.get 1)))
+          (func (export "write_entity_back") (param i64 i32) (result i32)
+            (call $write_entity_back (local.get 0) (local.get 1)))
+          (func (export "log") (param i32 i32 i32) (result i32)
+            (call $log (local.get 0) (local.get 1) (local.get 2)))
+          (func (export "input_state") (param i32) (result i32)
+            (call $input_state (local.get 0)))
+          (func (export "query_entities")
+            (param i32 i32 i32 i32) (result i32)
+            (call $query_entities
+              (local.get 0) (local.get 1) (local.get 2) (local.get 3)))
+          (func (export "register_model")
+            (param i32 i32 i32 i32 i32) (result i32)
+            (call $register_model (local.get 0) (local.get 1)
+              (local.get 2) (local.get 3) (local.get 4)))
+          (func (export "storage_get")
+            (param i32 i32 i32 i32 i32) (result i32)
+            (call $storage_get (local.get 0) (local.get 1)
+              (local.get 2) (local.get 3) (local.get 4)))
+          (func (export "storage_set") (param i32 i32 i32 i32) (result i32)
+            (call $storage_set
+              (local.get 0) (local.get 1) (local.get 2) (local.get 3)))
+          (func (export "storage_remove") (param i32 i32) (result i32)
+            (call $storage_remove (local.get 0) (local.get 1)))
+          (func (export "subscribe") (param i32 i32) (result i32)
+            (call $subscribe (local.get 0) (local.get 1)))
+          (func (export "unsubscribe") (param i32 i32) (result i32)
+            (call $unsubscribe (local.get 0) (local.get 1)))
+          (func (export "post") (param i32 i32 i32 i32 i32) (result i32)
+            (call $post (local.get 0) (local.get 1)
+              (local.get 2) (local.get 3) (local.get 4)))
+          (func (export "read_message") (param i32 i32) (result i32)
+            (call $read_message (local.get 0) (local.get 1))))
+    "#;
+
+    const PAGE: u32 = WASM_PAGE_SZ as u32;
+
+    /// Pointers to more than a byte that run off the end of memory, start
+    /// past it, or wrap around the address space
+    const OUT_OF_RANGE: [u32; 3] = [PAGE - 1, PAGE, u32::MAX - 1];
+
+    /// Pointer and length pairs that do the same, including a length that
+    /// wraps on its own
+    const BAD_RANGES: [(u32, u32); 4] =
+        [(PAGE - 1, 4), (PAGE, 1), (u32::MAX - 1, 4), (16, u32::MAX)];
+
+    /// Not aligned for any of the 4-byte aligned shared structs
+    const MISALIGNED: u32 = 2;
+
+    /// Pointers that must be rejected for a 4-byte aligned struct
+    fn bad_struct_ptrs() -> impl Iterator<Item = u32> {
+        OUT_OF_RANGE.into_iter().chain([MISALIGNED])
+    }
+
+    struct TestGuest {
+        store: Store<GuestState>,
+        instance: Instance,
+        data_dir: PathBuf,
+    }
+
+    impl TestGuest {
+        /// Instantiate `wat` against the real imports, as a module with the
+        /// storage capability and its own data directory
+        fn new(wat: &str, test: &str) -> Self {
+            let data_dir = std::env::temp_dir()
+                .join(format!("pipe-cleaner-{test}-{}", std::process::id()));
+
+            let config = Config {
+                fuel_per_frame: None,
+                data_dir: Some(data_dir.clone()),
+                cache_dir: None,
+                ..Config::default()
+            };
+
+            let manifest = Manifest {
+                name: "imports".into(),
+                version: "1".into(),
+                author: String::new(),
+                abi_version: ABI_VERSION,
+                capabilities: vec![Capability::Storage],
+            };
+
+            let engine = Engine::default();
+            let module = Module::new(&engine, wat).unwrap();
+            let world = Rc::new(RefCell::new(WasmWorld::default()));
+            let module_id = world.borrow_mut().register_module();
+
+            let mut store = new_store(
+                &engine,
+                Path::new("imports.wasm"),
+                Some(&manifest),
+                module_id,
+                &world,
+                &config,
+            );
+
+            let instance = link(&engine)
+                .unwrap()
+                .instantiate(&mut store, &module)
+                .unwrap();
+
+            Self {
+                store,
+                instance,
+                data_dir,
+            }
+        }
+
+        /// Call an export, which must not trap
+        fn call<P: WasmParams>(&mut self, name: &str, params: P) -> u32 {
+            self.instance
+                .get_typed_func::<P, u32>(&mut self.store, name)
+                .unwrap()
+                .call(&mut self.store, params)
+                .unwrap()
+        }
+
+        fn world(&self) -> &RefCell<WasmWorld> {
+            &self.store.data().world
+        }
+
+        /// Bits of a new entity owned by the guest
+        fn spawn(&self) -> u64 {
+            let module = self.store.data().module;
+            let mut world = self.world().borrow_mut();
+            world.create_entity(module).unwrap().bits()
+        }
+    }
+
+    impl Drop for TestGuest {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.data_dir);
+        }
+    }
+
+    #[test]
+    fn entity_imports_reject_bad_pointers() {
+        let mut guest = TestGuest::new(IMPORTS_WAT, "entity");
+        let handle = guest.spawn();
+
+        assert_eq!(guest.call("get_entity", (handle, 0u32)), 0);
+        assert_eq!(guest.call("write_entity_back", (handle, 0u32)), 0);
+
+        for ptr in bad_struct_ptrs() {
+            assert_eq!(guest.call("get_entity", (handle, ptr)), 1, "{ptr}");
+            assert_eq!(
+                guest.call("write_entity_back", (handle, ptr)),
+                1,
+                "{ptr}"
+            );
+        }
+    }
+
+    #[test]
+    fn log_rejects_bad_pointers() {
+        let mut guest = TestGuest::new(IMPORTS_WAT, "log");
+        let trace = LogLevel::Trace as u32;
+
+        assert_eq!(guest.call("log", (trace, 0u32, 4u32)), 0);
+
+        for (ptr, len) in BAD_RANGES {
+            assert_eq!(guest.call("log", (trace, ptr, len)), 1, "{ptr} {len}");
+        }
+    }
+
+    #[test]
+    fn input_state_rejects_bad_pointers() {
+        let mut guest = TestGuest::new(IMPORTS_WAT, "input");
+
+        assert_eq!(guest.call("input_state", 0u32), 0);
+
+        for ptr in bad_struct_ptrs() {
+            assert_eq!(guest.call("input_state", ptr), 1, "{ptr}");
+        }
+    }
+
+    #[test]
+    fn query_entities_rejects_bad_pointers() {
+        let mut guest = TestGuest::new(IMPORTS_WAT, "query");
+        guest.spawn();
+
+        let (query, out, cap, count) = (0u32, 2048u32, 4u32, 1024u32);
+        assert_eq!(guest.call("query_entities", (query, out, cap, count)), 0);
+
+        for ptr in bad_struct_ptrs() {
+            let params = (ptr, out, cap, count);
+            assert_eq!(guest.call("query_entities", params), 1, "{ptr}");
+
+            let params = (query, out, cap, ptr);
+            assert_eq!(guest.call("query_entities", params), 1, "{ptr}");
+        }
+
+        for (ptr, len) in BAD_RANGES {
+            let params = (query, ptr, len, count);
+            assert_eq!(guest.call("query_entities", params), 1, "{ptr} {len}");
+        }
+    }
+
+    #[test]
+    fn register_model_rejects_bad_pointers() {
+        let mut guest = TestGuest::new(IMPORTS_WAT, "model");
+        guest.world().borrow_mut().open_model_registration(0);
+
+        let (vertices, indices, model) = (0u32, 1024u32, 2048u32);
+        let params = (vertices, 2u32, indices, 2u32, model);
+        assert_eq!(guest.call("register_model", params), 0);
+
+        for (ptr, len) in BAD_RANGES {
+            let params = (ptr, len, indices, 2u32, model);
+            assert_eq!(guest.call("register_model", params), 1, "{ptr} {len}");
+
+            let params = (vertices, 2u32, ptr, len, model);
+            assert_eq!(guest.call("register_model", params), 1, "{ptr} {len}");
+        }
+
+        for ptr in bad_struct_ptrs() {
+            let params = (vertices, 2u32, indices, 2u32, ptr);
+            assert_eq!(guest.call("register_model", params), 1, "{ptr}");
+        }
+
+        // Only the good call registered anything
+        assert_eq!(guest.world().borrow_mut().take_models().len(), 1);
+    }
+
+    #[test]
+    fn storage_imports_reject_bad_pointers() {
+        let mut guest = TestGuest::new(IMPORTS_WAT, "storage");
+
+        // A one byte key and four byte value, both zeroes
+        let (key, value, buf, len) = (0u32, 16u32, 64u32, 32u32);
+        assert_eq!(guest.call("storage_set", (key, 1u32, value, 4u32)), 0);
+        assert_eq!(guest.call("storage_get", (key, 1u32, buf, 4u32, len)), 0);
+
+        for (ptr, ptr_len) in BAD_RANGES {
+            let params = (ptr, ptr_len, value, 4u32);
+            assert_eq!(guest.call("storage_set", params), 1, "{ptr}");
+
+            let params = (key, 1u32, ptr, ptr_len);
+            assert_eq!(guest.call("storage_set", params), 1, "{ptr}");
+
+            let params = (ptr, ptr_len, buf, 4u32, len);
+            assert_eq!(guest.call("storage_get", params), 1, "{ptr}");
+
+            let params = (key, 1u32, ptr, ptr_len.max(4), len);
+            assert_eq!(guest.call("storage_get", params), 1, "{ptr}");
+
+            let params = (ptr, ptr_len);
+            assert_eq!(guest.call("storage_remove", params), 1, "{ptr}");
+        }
+
+        for ptr in bad_struct_ptrs() {
+            let params = (key, 1u32, buf, 4u32, ptr);
+            assert_eq!(guest.call("storage_get", params), 1, "{ptr}");
+        }
+
+        assert_eq!(guest.call("storage_remove", (key, 1u32)), 0);
+    }
+
+    #[test]
+    fn message_imports_reject_bad_pointers() {
+        let mut guest = TestGuest::new(IMPORTS_WAT, "messages");
+
+        // A one byte topic and four byte message, both zeroes
+        let (topic, data) = (0u32, 16u32);
+        assert_eq!(guest.call("subscribe", (topic, 1u32)), 0);
+        assert_eq!(guest.call("unsubscribe", (topic, 1u32)), 0);
+        assert_eq!(guest.call("post", (topic, 1u32, 0u32, data, 4u32)), 0);
+
+        for (ptr, len) in BAD_RANGES {
+            assert_eq!(guest.call("subscribe", (ptr, len)), 1, "{ptr}");
+            assert_eq!(guest.call("unsubscribe", (ptr, len)), 1, "{ptr}");
+
+            let params = (ptr, len, 0u32, data, 4u32);
+            assert_eq!(guest.call("post", params), 1, "{ptr}");
+
+            let params = (topic, 1u32, 0u32, ptr, len);
+            assert_eq!(guest.call("post", params), 1, "{ptr}");
+        }
+
+        // Nothing to read outside of delivery
+        assert_eq!(guest.call("read_message", (topic, data)), 1);
+
+        guest.store.data_mut().message = Some(Message {
+            sender: guest.store.data().module,
+            topic: "topic".into(),
+            kind: 0,
+            data: vec![1, 2, 3, 4],
+        });
+
+        assert_eq!(guest.call("read_message", (topic, data)), 0);
+
+        for ptr in OUT_OF_RANGE {
+            assert_eq!(guest.call("read_message", (ptr, data)), 1, "{ptr}");
+            assert_eq!(guest.call("read_message", (topic, ptr)), 1, "{ptr}");
+        }
+    }
+
+    #[test]
+    fn imports_fail_without_exported_memory() {
+        let wat = r#"
+            (module
+              (import "env" "PIPECLEANER_log"
+                (func $log (param i32 i32 i32) (result i32)))
+              (memory 1)
+              (func (export "log") (param i32 i32 i32) (result i32)
+                (call $log (local.get 0) (local.get 1) (local.get 2))))
+        "#;
+
+        let mut guest = TestGuest::new(wat, "no-memory");
+        let trace = LogLevel::Trace as u32;
+        assert_eq!(guest.call("log", (trace, 0u32, 4u32)), 1);
+    }
+}
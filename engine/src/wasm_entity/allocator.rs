@@ -1,5 +1,9 @@
 use super::{Entity, Handle};
-use bytemuck::{Pod, Zeroable, must_cast, must_cast_mut, must_cast_ref};
+use crate::snapshot::{invalid, read_u32, write_u32};
+use bytemuck::{
+    Pod, Zeroable, cast_slice, must_cast, must_cast_mut, must_cast_ref,
+};
+use std::io::{self, Read, Write};
 use std::num::{NonZero, NonZeroU32};
 
 use pipe_cleaner_shared::{ENTITY_SZ, FIELD_SZ};
@@ -128,24 +132,37 @@ impl Allocator {
         })
     }
 
-    pub fn alloc(&mut self) -> Handle {
-        let idx = self.pop_free();
-        let id = self.next_id;
-        self.next_id = self.next_id.checked_add(1).unwrap();
+    /// Iterate over live entities along with their handles
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, &Entity)> {
+        self.memory
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter_map(|(idx, block)| {
+                let id = NonZeroU32::new(block.metadata.id)?;
+                let idx = NonZeroU32::new(u32::try_from(idx).ok()?)?;
+                let block = must_cast_ref::<_, OccupiedBlock>(block);
+                Some((Handle::new(id, idx), block.entity()))
+            })
+    }
 
+    /// Returns `None` once entity ids or block indices run out
+    pub fn alloc(&mut self) -> Option<Handle> {
+        let id = self.next_id;
+        let next_id = id.checked_add(1)?;
         let block = must_cast(OccupiedBlock::new(id));
 
-        if let Some(idx) = idx {
+        let idx = if let Some(idx) = self.pop_free() {
             self.memory[u32::from(idx) as usize] = block;
-            Handle::new(id, idx)
+            idx
         } else {
-            let idx =
-                NonZeroU32::new(u32::try_from(self.memory.len()).unwrap())
-                    .unwrap();
-
+            let idx = NonZeroU32::new(u32::try_from(self.memory.len()).ok()?)?;
             self.memory.push(block);
-            Handle::new(id, idx)
-        }
+            idx
+        };
+
+        self.next_id = next_id;
+        Some(Handle::new(id, idx))
     }
 
     pub fn free(&mut self, handle: Handle) -> bool {
@@ -159,17 +176,64 @@ impl Allocator {
         }
     }
 
-    fn pop_free<'a>(&'a mut self) -> Option<NonZeroU32> {
-        if let Some(idx) = self.free_head
-            && let Some(block) = self.memory.get(u32::from(idx) as usize)
-        {
-            let new_idx = must_cast_ref::<_, FreeBlock>(block).metadata.next;
+    /// Write every block, including free ones, along with the free list head
+    /// and next id so handles stay valid after `restore`
+    pub fn snapshot(&self, w: &mut impl Write) -> io::Result<()> {
+        write_u32(w, self.memory.len() as u32)?;
 
-            self.free_head = new_idx;
-            new_idx
-        } else {
-            None
+        for &word in cast_slice::<_, u32>(&self.memory) {
+            write_u32(w, word)?;
+        }
+
+        write_u32(w, self.free_head.map_or(0, u32::from))?;
+        write_u32(w, self.next_id.into())
+    }
+
+    pub fn restore(r: &mut impl Read) -> io::Result<Self> {
+        let block_ct = read_u32(r)? as usize;
+
+        // Blocks are pushed as they are read so a corrupt count can't make
+        // us allocate more than the file actually holds
+        let mut memory = Vec::new();
+
+        for _ in 0..block_ct {
+            let mut block = UnknownBlock::zeroed();
+
+            for word in must_cast_mut::<_, [u32; BLOCK_SZ]>(&mut block) {
+                *word = read_u32(r)?;
+            }
+
+            memory.push(block);
+        }
+
+        let free_head = NonZeroU32::new(read_u32(r)?);
+        let next_id = NonZeroU32::new(read_u32(r)?)
+            .ok_or_else(|| invalid("Allocator next id is 0"))?;
+
+        // Block 0 is never handed out, ids are only handed out below
+        // `next_id`, and the free list may only link free blocks, each once,
+        // or `alloc` would overwrite a live entity or hand out a block twice
+        if memory.first().is_none_or(|block| block.metadata.id != 0)
+            || memory
+                .iter()
+                .any(|block| block.metadata.id >= next_id.get())
+            || !free_list_is_sound(&memory, free_head)
+        {
+            return Err(invalid("Allocator blocks are corrupt"));
         }
+
+        Ok(Self {
+            memory,
+            free_head,
+            next_id,
+        })
+    }
+
+    fn pop_free(&mut self) -> Option<NonZeroU32> {
+        let idx = self.free_head?;
+        let block = self.memory.get(u32::from(idx) as usize)?;
+        self.free_head = must_cast_ref::<_, FreeBlock>(block).metadata.next;
+        Some(idx)
     }
 
     fn get_occupied_block(&self, handle: Handle) -> Option<&OccupiedBlock> {
@@ -200,6 +264,29 @@ impl Allocator {
     }
 }
 
+/// Whether the free list starting at `head` only visits free blocks, never
+/// visits one twice and ends inside `memory`
+fn free_list_is_sound(
+    memory: &[UnknownBlock],
+    mut head: Option<NonZeroU32>,
+) -> bool {
+    let mut visited = vec![false; memory.len()];
+
+    while let Some(idx) = head {
+        let idx = u32::from(idx) as usize;
+
+        match memory.get(idx) {
+            Some(block) if block.metadata.id == 0 && !visited[idx] => {
+                visited[idx] = true;
+                head = must_cast_ref::<_, FreeBlock>(block).metadata.next;
+            }
+            _ => return false,
+        }
+    }
+
+    true
+}
+
 impl Default for Allocator {
     fn default() -> Self {
         Self {
@@ -209,3 +296,109 @@ impl Default for Allocator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(allocator: &Allocator) -> Vec<u32> {
+        let mut bytes = Vec::new();
+        allocator.snapshot(&mut bytes).unwrap();
+
+        bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    fn restore(words: &[u32]) -> io::Result<Allocator> {
+        Allocator::restore(&mut cast_slice::<_, u8>(words))
+    }
+
+    #[test]
+    fn restore_round_trips() {
+        let mut allocator = Allocator::default();
+        let a = allocator.alloc().unwrap();
+        let b = allocator.alloc().unwrap();
+        let c = allocator.alloc().unwrap();
+        allocator.entity_mut(c).unwrap().engine_fields.model = 7;
+        allocator.free(b);
+
+        let mut restored = restore(&snapshot(&allocator)).unwrap();
+
+        assert!(restored.entity(a).is_some());
+        assert!(restored.entity(b).is_none());
+        assert_eq!(restored.entity(c).unwrap().engine_fields.model, 7);
+
+        // The free list and ids carry over, so the next handle matches
+        assert_eq!(restored.alloc(), allocator.alloc());
+    }
+
+    #[test]
+    fn restore_rejects_huge_block_count() {
+        let mut words = snapshot(&Allocator::default());
+        words[0] = u32::MAX;
+
+        let error = restore(&words).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn restore_rejects_corrupt_blocks() {
+        let mut allocator = Allocator::default();
+        let handle = allocator.alloc().unwrap();
+        allocator.alloc().unwrap();
+        allocator.free(handle);
+        let words = snapshot(&allocator);
+        let block_ct = words[0] as usize;
+        let free_head = 1 + block_ct * BLOCK_SZ;
+
+        let corrupt = |idx: usize, word: u32| {
+            let mut words = words.clone();
+            words[idx] = word;
+            restore(&words).err().unwrap().kind()
+        };
+
+        // Block 0 must stay free
+        assert_eq!(corrupt(1, 5), io::ErrorKind::InvalidData);
+        // Free list head, and the link in the freed block, out of bounds
+        assert_eq!(corrupt(free_head, 99), io::ErrorKind::InvalidData);
+        assert_eq!(corrupt(1 + BLOCK_SZ + 1, 99), io::ErrorKind::InvalidData);
+        // Next id of 0
+        assert_eq!(corrupt(free_head + 1, 0), io::ErrorKind::InvalidData);
+        // Free list head on the live block 2
+        assert_eq!(corrupt(free_head, 2), io::ErrorKind::InvalidData);
+        // Freed block linking to itself
+        assert_eq!(corrupt(1 + BLOCK_SZ + 1, 1), io::ErrorKind::InvalidData);
+        // Live block with an id that hasn't been handed out yet
+        assert_eq!(corrupt(1 + 2 * BLOCK_SZ, 3), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn restore_rejects_free_list_cycles() {
+        let mut allocator = Allocator::default();
+        let handles = [(); 3].map(|()| allocator.alloc().unwrap());
+
+        for handle in handles {
+            allocator.free(handle);
+        }
+
+        // The list runs 3 -> 2 -> 1; point 1 back at 3
+        let mut words = snapshot(&allocator);
+        words[1 + BLOCK_SZ + 1] = 3;
+
+        let error = restore(&words).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn restore_rejects_truncated_input() {
+        let mut allocator = Allocator::default();
+        allocator.alloc().unwrap();
+        let words = snapshot(&allocator);
+
+        for len in 0..words.len() {
+            assert!(restore(&words[..len]).is_err(), "{len}");
+        }
+    }
+}
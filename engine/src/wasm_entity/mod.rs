@@ -1,12 +1,15 @@
 mod allocator;
 
+use crate::{entity, visual};
 pub use allocator::Allocator;
 use std::num::{NonZero, NonZeroU32};
 
 pub use pipe_cleaner_shared::{EngineFields, Entity};
 
+/// Handles order by block index and then id, which is the order entities
+/// were created in as long as none have been removed
 #[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Handle {
     bits: u64,
 }
@@ -15,19 +18,7 @@ impl Handle {
     pub fn from_bits(bits: u64) -> Option<Self> {
         let (id, idx) = (Self::bits_to_id(bits), Self::bits_to_index(bits));
 
-        let id = if let Some(id) = NonZero::new(id) {
-            id
-        } else {
-            return None;
-        };
-
-        let idx = if let Some(idx) = NonZero::new(idx) {
-            idx
-        } else {
-            return None;
-        };
-
-        Some(Self::new(id, idx))
+        Some(Self::new(NonZero::new(id)?, NonZero::new(idx)?))
     }
 
     pub fn new(id: NonZeroU32, index: NonZeroU32) -> Self {
@@ -58,27 +49,16 @@ impl Handle {
     }
 }
 
-const PIPE_RADIUS: f32 = 1.0;
-
-/*
 impl visual::Instance for Entity {
-    #[rustfmt::skip]
-    fn transform(&self) -> [f32; 12] {
-        let (sin, cos) = self.position.angle.sin_cos();
-
-        [
-            cos,  sin,  0f32, PIPE_RADIUS*cos,
-            sin, -cos,  0f32, PIPE_RADIUS*sin,
-            0f32, 0f32, 1f32, self.position.depth,
-        ]
+    fn transform(&self) -> visual::TransformMatrix {
+        entity::pipe_transform(self.engine_fields.position)
     }
 
-    fn color(&self) -> [f32; 3] {
-        self.color
+    fn color(&self) -> visual::Color {
+        self.engine_fields.color
     }
 
     fn model(&self) -> usize {
-        self.model
+        self.engine_fields.model as usize
     }
 }
-*/
@@ -1,6 +1,5 @@
-use crate::FRAME_DURATION_F32;
-use crate::{PipePosition, entity, visual};
-use entity::EntRef;
+use crate::{entity, visual};
+use entity::{EntRef, PipePosition};
 use std::cell::RefCell;
 use std::rc::Rc;
 use visual::WorldPosition;
@@ -12,6 +11,8 @@ const ZOOM_SPEED: f32 = 6.0;
 pub struct World {
     rings: Vec<RingInstance>,
     ent_mgr: entity::Manager,
+    wasm_world: Rc<RefCell<WasmWorld>>,
+    guest_entities: Vec<Entity>,
     progress: Rc<RefCell<f32>>,
 }
 
@@ -36,10 +37,17 @@ impl World {
         Self {
             rings,
             ent_mgr: Default::default(),
+            wasm_world: Default::default(),
+            guest_entities: Vec::new(),
             progress,
         }
     }
 
+    /// World shared with wasm hosts, holding entities created by guests
+    pub fn wasm_world(&self) -> Rc<RefCell<WasmWorld>> {
+        Rc::clone(&self.wasm_world)
+    }
+
     pub fn geometry<'a>(
         &'a self,
     ) -> impl Iterator<Item = &'a (dyn visual::Instance + 'a)> {
@@ -47,6 +55,11 @@ impl World {
             .iter()
             .map(|r| r as &'a (dyn visual::Instance + 'a))
             .chain(self.ent_mgr.iter_visual())
+            .chain(
+                self.guest_entities
+                    .iter()
+                    .map(|e| e as &'a (dyn visual::Instance + 'a)),
+            )
     }
 
     pub fn place_entity(&mut self, position: PipePosition) -> entity::EntRef {
@@ -59,10 +72,13 @@ impl World {
         self.ent_mgr.remove(&entity);
     }
 
-    pub fn update(&mut self) {
+    /// Advance the world by `dt` seconds
+    pub fn update(&mut self, dt: f64) {
         self.update_logic();
-        self.update_physics();
-        *self.progress.borrow_mut() += FRAME_DURATION_F32;
+        self.update_physics(dt as f32);
+        self.update_guest_entities();
+        self.wasm_world.borrow_mut().advance_clock(dt);
+        *self.progress.borrow_mut() += dt as f32;
     }
 
     fn update_logic(&mut self) {
@@ -75,38 +91,40 @@ impl World {
         }
     }
 
-    fn update_physics(&self) {
+    fn update_physics(&self, dt: f32) {
         for ent in self.ent_mgr.iter() {
             let mut ent = ent.borrow_mut();
+            let ent = &mut *ent;
 
-            let [mut vel_angular, vel_depth] = ent.velocity;
-            let [targ_vel_angular, _] = ent.target_velocity;
-
-            let accel = if targ_vel_angular > vel_angular {
-                ent.max_acceleration
-            } else if targ_vel_angular < vel_angular {
-                -ent.max_acceleration
-            } else {
-                0.0
-            };
-
-            ent.position.angle +=
-                0.5 * FRAME_DURATION_F32 * FRAME_DURATION_F32 * accel
-                    + FRAME_DURATION_F32 * vel_angular;
-
-            ent.position.depth += FRAME_DURATION_F32 * vel_depth;
+            entity::integrate(
+                &mut ent.position,
+                &mut ent.velocity,
+                ent.target_velocity,
+                ent.max_acceleration,
+                dt,
+            );
+        }
 
-            if targ_vel_angular > vel_angular {
-                vel_angular += FRAME_DURATION_F32 * ent.max_acceleration;
-                vel_angular = vel_angular.min(targ_vel_angular);
-            } else if targ_vel_angular < vel_angular {
-                vel_angular -= FRAME_DURATION_F32 * ent.max_acceleration;
-                vel_angular = vel_angular.max(targ_vel_angular);
-            }
+        for ent in self.wasm_world.borrow_mut().entity_iter_mut() {
+            let fields = &mut ent.engine_fields;
 
-            ent.velocity = [vel_angular, vel_depth];
+            entity::integrate(
+                &mut fields.position,
+                &mut fields.velocity,
+                fields.target_velocity,
+                fields.max_acceleration,
+                dt,
+            );
         }
     }
+
+    /// Copy guest entities out of the wasm world so they can be rendered
+    /// without holding a borrow on it
+    fn update_guest_entities(&mut self) {
+        self.guest_entities.clear();
+        self.guest_entities
+            .extend(self.wasm_world.borrow().entity_iter().copied());
+    }
 }
 
 #[derive(Clone)]
@@ -152,73 +170,617 @@ impl visual::Instance for RingInstance {
     }
 }
 
+use crate::rng::Rng;
+use crate::snapshot::{
+    invalid, read_bytes, read_u32, read_u64, write_bytes, write_u32, write_u64,
+};
 use crate::wasm_entity::{Allocator, EngineFields, Entity, Handle};
-use bytemuck::{cast_slice_mut, must_cast_mut, must_cast_ref};
+use pipe_cleaner_shared::{
+    EntityQuery, InputState, MAX_MESSAGE_SZ, MAX_MODEL_INDICES,
+    MAX_MODEL_VERTICES, MAX_TOPIC_SZ, QueryFilter,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::f32::consts::TAU;
+use std::io::{self, Read, Write};
+
+/// Most guest entities alive at once, across every module.  The renderer
+/// sizes its instance buffer from this.
+pub const MAX_GUEST_ENTITIES: usize = 8192;
+
+/// Identifies a game module loaded into the wasm world
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ModuleId(u32);
 
 #[derive(Default)]
 pub struct WasmWorld {
     allocator: Allocator,
+    owners: HashMap<Handle, ModuleId>,
+    module_ct: u32,
+    input: InputState,
+    models: Vec<visual::BaseMesh>,
+    model_base: Option<usize>,
+    /// Ordered so thinks run in the same order on every run
+    thinks: BTreeMap<Handle, u32>,
+    rng: Rng,
+    clock: f64,
+    timers: BTreeMap<u64, Timer>,
+    last_timer_id: u64,
+    subscribers: HashMap<String, HashSet<ModuleId>>,
+    outbox: Vec<Message>,
+}
+
+/// Bytes posted by a guest to a topic, delivered to the topic's other
+/// subscribers on the next tick
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub sender: ModuleId,
+    pub topic: String,
+    /// Tag chosen by the sender to say what the data means
+    pub kind: u32,
+    pub data: Vec<u8>,
+}
+
+/// Callback scheduled by a guest, passed back to the module that scheduled
+/// it once the world's clock reaches `due`
+#[derive(Clone, Copy, Debug)]
+pub struct Timer {
+    pub module: ModuleId,
+    pub handle: Option<Handle>,
+    pub callback: u32,
+    due: f64,
 }
 
 impl WasmWorld {
-    pub fn create_entity(&mut self) -> Handle {
-        self.allocator.alloc()
+    /// Let guests register models, numbering them from `first_id`
+    pub fn open_model_registration(&mut self, first_id: usize) {
+        self.model_base = Some(first_id);
+    }
+
+    /// Add a line-segment mesh submitted by a guest, returning its model id.
+    /// Fails if registration is closed or the mesh is malformed.
+    pub fn register_model(&mut self, mesh: visual::BaseMesh) -> Option<u32> {
+        let model_base = self.model_base?;
+        let vertex_ct = mesh.vertices.len();
+
+        if vertex_ct > MAX_MODEL_VERTICES
+            || mesh.indices.len() > MAX_MODEL_INDICES
+            || !mesh.indices.len().is_multiple_of(2)
+            || mesh.indices.iter().any(|&idx| idx as usize >= vertex_ct)
+        {
+            return None;
+        }
+
+        let id = u32::try_from(model_base + self.models.len()).ok()?;
+        self.models.push(mesh);
+        Some(id)
+    }
+
+    /// Close registration and hand over the meshes registered by guests, in
+    /// id order
+    pub fn take_models(&mut self) -> Vec<visual::BaseMesh> {
+        self.model_base = None;
+        std::mem::take(&mut self.models)
+    }
+
+    /// Set the buttons held this tick, as a bitmask of `Button`s
+    pub fn update_input(&mut self, held: u32) {
+        let pressed = held & !self.input.held;
+        self.input = InputState { held, pressed };
+    }
+
+    pub fn input(&self) -> InputState {
+        self.input
+    }
+
+    /// Restart the random number sequence shared by all guests
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn random(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    pub fn register_module(&mut self) -> ModuleId {
+        let id = ModuleId(self.module_ct);
+        self.module_ct += 1;
+        id
+    }
+
+    /// Returns `None` once `MAX_GUEST_ENTITIES` are alive or the allocator
+    /// runs out
+    pub fn create_entity(&mut self, owner: ModuleId) -> Option<Handle> {
+        if self.owners.len() >= MAX_GUEST_ENTITIES {
+            return None;
+        }
+
+        let handle = self.allocator.alloc()?;
+        self.owners.insert(handle, owner);
+        Some(handle)
+    }
+
+    /// Remove everything belonging to a module that has been unloaded: its
+    /// entities and their thinks and timers, its own timers, subscriptions
+    /// and undelivered messages
+    pub fn unload_module(&mut self, module: ModuleId) {
+        let mut owned = self
+            .owners
+            .iter()
+            .filter(|&(_, &owner)| owner == module)
+            .map(|(&handle, _)| handle)
+            .collect::<Vec<_>>();
+
+        // The free list is LIFO, so the order of frees decides the handles
+        // handed out later
+        owned.sort();
+
+        for handle in owned {
+            self.remove_entity(handle);
+        }
+
+        self.timers.retain(|_, timer| timer.module != module);
+        self.outbox.retain(|message| message.sender != module);
+
+        self.subscribers.retain(|_, modules| {
+            modules.remove(&module);
+            !modules.is_empty()
+        });
+    }
+
+    /// Number of live entities created by the module
+    pub fn owned_count(&self, module: ModuleId) -> usize {
+        self.owners
+            .values()
+            .filter(|&&owner| owner == module)
+            .count()
     }
 
     pub fn remove_entity(&mut self, handle: Handle) -> bool {
+        self.owners.remove(&handle);
+        self.thinks.remove(&handle);
+        self.timers.retain(|_, timer| timer.handle != Some(handle));
         self.allocator.free(handle)
     }
 
-    pub fn write_entity_to_guest(
-        &self,
-        handle: Handle,
-        guest_memory: &mut [u8],
-    ) -> bool {
-        if std::mem::align_of_val(guest_memory) >= 4 {
+    /// Handles of entities matching the query made by the given module
+    pub fn query<'a>(
+        &'a self,
+        query: &'a EntityQuery,
+        requester: ModuleId,
+    ) -> impl Iterator<Item = Handle> + 'a {
+        let enabled = |filter: QueryFilter| query.filters & filter as u32 != 0;
+
+        self.allocator.iter().filter_map(move |(handle, entity)| {
+            let fields = &entity.engine_fields;
+
+            if enabled(QueryFilter::Model) && fields.model != query.model {
+                return None;
+            }
+
+            if enabled(QueryFilter::Window) {
+                let angle_diff =
+                    angle_between(fields.position.angle, query.position.angle);
+                let depth_diff =
+                    (fields.position.depth - query.position.depth).abs();
+
+                if angle_diff > query.angle_range
+                    || depth_diff > query.depth_range
+                {
+                    return None;
+                }
+            }
+
+            if enabled(QueryFilter::Owned)
+                && self.owner(handle) != Some(requester)
+            {
+                return None;
+            }
+
+            Some(handle)
+        })
+    }
+
+    /// Pairs of entities whose collision circles overlap, where at least
+    /// one of them has the other's layers in its collision mask.  Pairs are
+    /// in the order of a pairwise check over entities in handle order.
+    pub fn collisions(&self) -> Vec<(Handle, Handle)> {
+        let colliders = self
+            .allocator
+            .iter()
+            .map(|(handle, e)| (handle, e.engine_fields))
+            .filter(|(_, fields)| {
+                fields.collision_layers != 0 || fields.collision_mask != 0
+            })
+            .collect::<Vec<_>>();
+
+        // Sort and sweep along the pipe: circles further apart in depth
+        // than their radii can't overlap, so each entity only has to be
+        // checked against the ones just past it
+        let mut by_depth = (0..colliders.len()).collect::<Vec<_>>();
+        by_depth.sort_by(|&a, &b| {
+            let depth = |idx: usize| colliders[idx].1.position.depth;
+            depth(a).total_cmp(&depth(b))
+        });
+
+        let max_radius = colliders
+            .iter()
+            .map(|(_, fields)| fields.collision_radius.abs())
+            .fold(0.0, f32::max);
+
+        let mut pairs = Vec::new();
+
+        for (sorted_idx, &idx_a) in by_depth.iter().enumerate() {
+            let a = &colliders[idx_a].1;
+            let window = a.collision_radius.abs() + max_radius;
+
+            for &idx_b in &by_depth[sorted_idx + 1..] {
+                let b = &colliders[idx_b].1;
+
+                if b.position.depth - a.position.depth >= window {
+                    break;
+                }
+
+                if overlap(a, b) {
+                    pairs.push((idx_a.min(idx_b), idx_a.max(idx_b)));
+                }
+            }
+        }
+
+        pairs.sort_unstable();
+
+        pairs
+            .into_iter()
+            .map(|(a, b)| (colliders[a].0, colliders[b].0))
+            .collect()
+    }
+
+    /// Run the function at `think` in the owning module's function table for
+    /// the entity every tick, or stop if `think` is `None`.  Returns false if
+    /// the entity doesn't exist.
+    pub fn set_think(&mut self, handle: Handle, think: Option<u32>) -> bool {
+        if !self.owners.contains_key(&handle) {
             return false;
         }
 
-        self.allocator
-            .entity(handle)
-            .inspect(|&e| {
-                let entity_bytes: &[u8; size_of::<Entity>()] = must_cast_ref(e);
+        match think {
+            Some(think) => self.thinks.insert(handle, think),
+            None => self.thinks.remove(&handle),
+        };
 
-                guest_memory.copy_from_slice(entity_bytes);
+        true
+    }
 
-                let engine_bytes =
-                    &mut guest_memory[..size_of::<EngineFields>()];
+    pub fn think(&self, handle: Handle) -> Option<u32> {
+        self.thinks.get(&handle).copied()
+    }
 
-                let engine_fields = cast_slice_mut::<_, u32>(engine_bytes);
+    /// Think functions of the entities owned by the module, in handle order
+    pub fn thinks(&self, module: ModuleId) -> Vec<(Handle, u32)> {
+        self.thinks
+            .iter()
+            .filter(|(handle, _)| self.owner(**handle) == Some(module))
+            .map(|(&handle, &think)| (handle, think))
+            .collect()
+    }
 
-                for field in engine_fields {
-                    *field = field.to_le();
-                }
-            })
-            .is_some()
+    /// Forget the think functions of the module's entities, returning them.
+    /// Needed when the module is reloaded, since its function table changes.
+    pub fn take_thinks(&mut self, module: ModuleId) -> Vec<(Handle, u32)> {
+        let thinks = self.thinks(module);
+
+        for (handle, _) in &thinks {
+            self.thinks.remove(handle);
+        }
+
+        thinks
     }
 
-    pub fn read_entity_from_guest(
+    /// Move the clock timers are measured against forward by `dt` seconds
+    pub fn advance_clock(&mut self, dt: f64) {
+        self.clock += dt;
+    }
+
+    /// Schedule `callback` for `delay` seconds from now, returning the timer's
+    /// id.  Timers tied to an entity are dropped when it is removed.  Fails if
+    /// the entity doesn't exist or the delay isn't a finite, non-negative
+    /// number.
+    pub fn schedule(
         &mut self,
-        handle: Handle,
-        guest_memory: &[u8],
-    ) -> bool {
-        if let Some(e) = self.allocator.entity_mut(handle) {
-            let entity_bytes: &mut [u8; size_of::<Entity>()] = must_cast_mut(e);
+        module: ModuleId,
+        handle: Option<Handle>,
+        delay: f32,
+        callback: u32,
+    ) -> Option<u64> {
+        if !delay.is_finite()
+            || delay < 0.0
+            || handle.is_some_and(|handle| !self.owners.contains_key(&handle))
+        {
+            return None;
+        }
+
+        self.last_timer_id += 1;
+
+        let timer = Timer {
+            module,
+            handle,
+            callback,
+            due: self.clock + delay as f64,
+        };
+
+        self.timers.insert(self.last_timer_id, timer);
+        Some(self.last_timer_id)
+    }
+
+    /// Cancel a timer scheduled by the module.  Returns false if there is no
+    /// such pending timer.
+    pub fn cancel_timer(&mut self, module: ModuleId, id: u64) -> bool {
+        match self.timers.get(&id) {
+            Some(timer) if timer.module == module => {
+                self.timers.remove(&id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove and return the timers that are due, earliest first
+    pub fn take_due_timers(&mut self) -> Vec<Timer> {
+        let mut due = self
+            .timers
+            .iter()
+            .filter(|(_, timer)| timer.due <= self.clock)
+            .map(|(&id, &timer)| (id, timer))
+            .collect::<Vec<_>>();
+
+        due.sort_by(|(a_id, a), (b_id, b)| {
+            a.due.total_cmp(&b.due).then(a_id.cmp(b_id))
+        });
+
+        for (id, _) in &due {
+            self.timers.remove(id);
+        }
+
+        due.into_iter().map(|(_, timer)| timer).collect()
+    }
+
+    /// Returns false if the module was already subscribed
+    pub fn subscribe(&mut self, module: ModuleId, topic: &str) -> bool {
+        self.subscribers
+            .entry(topic.to_owned())
+            .or_default()
+            .insert(module)
+    }
+
+    /// Returns false if the module wasn't subscribed
+    pub fn unsubscribe(&mut self, module: ModuleId, topic: &str) -> bool {
+        let Some(modules) = self.subscribers.get_mut(topic) else {
+            return false;
+        };
+
+        let removed = modules.remove(&module);
+
+        if modules.is_empty() {
+            self.subscribers.remove(topic);
+        }
+
+        removed
+    }
+
+    pub fn is_subscribed(&self, module: ModuleId, topic: &str) -> bool {
+        self.subscribers
+            .get(topic)
+            .is_some_and(|modules| modules.contains(&module))
+    }
+
+    /// Queue a message for delivery on the next tick
+    pub fn post(&mut self, message: Message) {
+        self.outbox.push(message);
+    }
+
+    /// Number of messages the module has posted since the last
+    /// `take_messages`
+    pub fn pending_count(&self, module: ModuleId) -> usize {
+        self.outbox
+            .iter()
+            .filter(|msg| msg.sender == module)
+            .count()
+    }
+
+    /// Remove and return the queued messages, in the order they were posted
+    pub fn take_messages(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Write the state guests can observe, except for models, which are
+    /// fixed at startup, and input, which is replaced every tick.  Maps are
+    /// written in sorted order so equal worlds give equal bytes.
+    pub fn snapshot(&self, w: &mut impl Write) -> io::Result<()> {
+        self.allocator.snapshot(w)?;
+        write_u32(w, self.module_ct)?;
+        write_u32(w, self.owners.len() as u32)?;
+
+        let mut owners = self.owners.iter().collect::<Vec<_>>();
+        owners.sort();
+
+        for (handle, module) in owners {
+            write_u64(w, handle.bits())?;
+            write_u32(w, module.0)?;
+        }
+
+        write_u32(w, self.thinks.len() as u32)?;
+
+        for (handle, &think) in &self.thinks {
+            write_u64(w, handle.bits())?;
+            write_u32(w, think)?;
+        }
+
+        write_u64(w, self.rng.state())?;
+        write_u64(w, self.clock.to_bits())?;
+        write_u32(w, self.timers.len() as u32)?;
+
+        for (&id, timer) in &self.timers {
+            write_u64(w, id)?;
+            write_u32(w, timer.module.0)?;
+            write_u64(w, timer.handle.map_or(0, |handle| handle.bits()))?;
+            write_u32(w, timer.callback)?;
+            write_u64(w, timer.due.to_bits())?;
+        }
 
-            entity_bytes.copy_from_slice(guest_memory);
+        write_u64(w, self.last_timer_id)?;
+        write_u32(w, self.subscribers.len() as u32)?;
 
-            let engine_bytes = &mut entity_bytes[..size_of::<EngineFields>()];
+        let mut subscribers = self.subscribers.iter().collect::<Vec<_>>();
+        subscribers.sort_by_key(|&(topic, _)| topic);
 
-            let engine_fields = cast_slice_mut::<_, u32>(engine_bytes);
+        for (topic, modules) in subscribers {
+            write_bytes(w, topic.as_bytes())?;
+            write_u32(w, modules.len() as u32)?;
 
-            for field in engine_fields {
-                *field = u32::from_le(*field);
+            let mut modules = modules.iter().collect::<Vec<_>>();
+            modules.sort();
+
+            for module in modules {
+                write_u32(w, module.0)?;
+            }
+        }
+
+        write_u32(w, self.outbox.len() as u32)?;
+
+        for message in &self.outbox {
+            write_u32(w, message.sender.0)?;
+            write_bytes(w, message.topic.as_bytes())?;
+            write_u32(w, message.kind)?;
+            write_bytes(w, &message.data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the state written by `snapshot`.  Nothing changes if the
+    /// snapshot is invalid or was taken with a different number of modules.
+    pub fn restore(&mut self, r: &mut impl Read) -> io::Result<()> {
+        let allocator = Allocator::restore(r)?;
+
+        if read_u32(r)? != self.module_ct {
+            return Err(invalid("Snapshot has a different number of modules"));
+        }
+
+        let read_module = |r: &mut _| {
+            let module = read_u32(r)?;
+
+            if module < self.module_ct {
+                Ok(ModuleId(module))
+            } else {
+                Err(invalid(format!("Bad module id {module}")))
+            }
+        };
+
+        let mut owners = HashMap::new();
+
+        for _ in 0..read_u32(r)? {
+            let handle = read_handle(r)?;
+
+            if allocator.entity(handle).is_none() {
+                return Err(invalid(format!("Dead entity {handle:?}")));
+            }
+
+            owners.insert(handle, read_module(r)?);
+        }
+
+        let mut thinks = BTreeMap::new();
+
+        // `owners` only holds live entities of loaded modules, so checking
+        // against it covers thinks and timers too
+        let owned = |handle: Handle| {
+            if owners.contains_key(&handle) {
+                Ok(handle)
+            } else {
+                Err(invalid(format!("Unowned entity {handle:?}")))
+            }
+        };
+
+        for _ in 0..read_u32(r)? {
+            thinks.insert(owned(read_handle(r)?)?, read_u32(r)?);
+        }
+
+        let rng = Rng::new(read_u64(r)?);
+        let clock = f64::from_bits(read_u64(r)?);
+        let mut timers = BTreeMap::new();
+
+        for _ in 0..read_u32(r)? {
+            let id = read_u64(r)?;
+            let module = read_module(r)?;
+
+            let handle = match read_u64(r)? {
+                0 => None,
+                bits => Some(owned(
+                    Handle::from_bits(bits)
+                        .ok_or_else(|| invalid("Bad timer handle"))?,
+                )?),
+            };
+
+            let timer = Timer {
+                module,
+                handle,
+                callback: read_u32(r)?,
+                due: f64::from_bits(read_u64(r)?),
+            };
+
+            timers.insert(id, timer);
+        }
+
+        let last_timer_id = read_u64(r)?;
+        let mut subscribers = HashMap::<_, HashSet<_>>::new();
+
+        for _ in 0..read_u32(r)? {
+            let topic = read_topic(r)?;
+
+            for _ in 0..read_u32(r)? {
+                subscribers
+                    .entry(topic.clone())
+                    .or_default()
+                    .insert(read_module(r)?);
             }
+        }
 
-            true
-        } else {
-            false
+        let mut outbox = Vec::new();
+
+        for _ in 0..read_u32(r)? {
+            outbox.push(Message {
+                sender: read_module(r)?,
+                topic: read_topic(r)?,
+                kind: read_u32(r)?,
+                data: read_bytes(r, MAX_MESSAGE_SZ)?,
+            });
         }
+
+        self.allocator = allocator;
+        self.owners = owners;
+        self.thinks = thinks;
+        self.rng = rng;
+        self.clock = clock;
+        self.timers = timers;
+        self.last_timer_id = last_timer_id;
+        self.subscribers = subscribers;
+        self.outbox = outbox;
+        Ok(())
+    }
+
+    /// Module that created the entity, if it is still alive
+    pub fn owner(&self, handle: Handle) -> Option<ModuleId> {
+        self.owners.get(&handle).copied()
+    }
+
+    pub fn entity(&self, handle: Handle) -> Option<Entity> {
+        self.allocator.entity(handle).copied()
+    }
+
+    /// Overwrite a live entity.  Returns false if the handle is stale.
+    pub fn set_entity(&mut self, handle: Handle, entity: Entity) -> bool {
+        self.allocator
+            .entity_mut(handle)
+            .map(|e| *e = entity)
+            .is_some()
     }
 
     pub fn entity_iter(&self) -> impl Iterator<Item=&Entity> {
@@ -229,3 +791,307 @@ impl WasmWorld {
         self.allocator.entity_iter_mut()
     }
 }
+
+fn read_handle(r: &mut impl Read) -> io::Result<Handle> {
+    let bits = read_u64(r)?;
+    Handle::from_bits(bits)
+        .ok_or_else(|| invalid(format!("Bad handle {bits:#x}")))
+}
+
+fn read_topic(r: &mut impl Read) -> io::Result<String> {
+    String::from_utf8(read_bytes(r, MAX_TOPIC_SZ)?)
+        .map_err(|_| invalid("Topic isn't UTF-8"))
+}
+
+/// Smallest angle between two angles, in radians
+/// Whether two entities' collision circles overlap, and at least one of them
+/// collides with the other's layers
+fn overlap(a: &EngineFields, b: &EngineFields) -> bool {
+    if a.collision_mask & b.collision_layers == 0
+        && b.collision_mask & a.collision_layers == 0
+    {
+        return false;
+    }
+
+    let arc =
+        entity::PIPE_RADIUS * angle_between(a.position.angle, b.position.angle);
+    let depth = a.position.depth - b.position.depth;
+    let reach = a.collision_radius + b.collision_radius;
+
+    arc * arc + depth * depth < reach * reach
+}
+
+fn angle_between(a: f32, b: f32) -> f32 {
+    let diff = (a - b).rem_euclid(TAU);
+    diff.min(TAU - diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(world: &WasmWorld) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        world.snapshot(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// A world with two modules, and an entity, think, timer, subscription
+    /// and message belonging to the first
+    fn populated() -> (WasmWorld, Handle) {
+        let mut world = WasmWorld::default();
+        let module = world.register_module();
+        world.register_module();
+
+        let handle = world.create_entity(module).unwrap();
+        world.set_think(handle, Some(3));
+        world.schedule(module, Some(handle), 1.0, 4).unwrap();
+        world.subscribe(module, "topic");
+        world.post(Message {
+            sender: module,
+            topic: "topic".into(),
+            kind: 5,
+            data: vec![1, 2, 3],
+        });
+
+        (world, handle)
+    }
+
+    #[test]
+    fn collisions_match_checking_every_pair() {
+        let mut world = WasmWorld::default();
+        let module = world.register_module();
+        let mut rng = Rng::new(9);
+        let mut unit = || (rng.next_u64() >> 40) as f32 / (1 << 24) as f32;
+
+        let handles = (0..400)
+            .map(|_| world.create_entity(module).unwrap())
+            .collect::<Vec<_>>();
+
+        // Free some in the middle, so later handles reuse their blocks and
+        // handle order isn't creation order
+        for &handle in handles.iter().step_by(7) {
+            world.remove_entity(handle);
+        }
+        for _ in 0..30 {
+            world.create_entity(module).unwrap();
+        }
+
+        let live = world.owners.keys().copied().collect::<Vec<_>>();
+
+        for handle in live {
+            let mut entity = world.entity(handle).unwrap();
+            let fields = &mut entity.engine_fields;
+            fields.position.angle = unit() * TAU;
+            fields.position.depth = unit() * 40.0;
+            fields.collision_radius = unit() * 1.5;
+            fields.collision_layers = (unit() * 4.0) as u32;
+            fields.collision_mask = (unit() * 4.0) as u32;
+            world.set_entity(handle, entity);
+        }
+
+        let colliders = world
+            .allocator
+            .iter()
+            .filter(|(_, e)| {
+                let fields = &e.engine_fields;
+                fields.collision_layers != 0 || fields.collision_mask != 0
+            })
+            .collect::<Vec<_>>();
+
+        let mut expected = Vec::new();
+
+        for (idx, (handle_a, a)) in colliders.iter().enumerate() {
+            for (handle_b, b) in &colliders[idx + 1..] {
+                if overlap(&a.engine_fields, &b.engine_fields) {
+                    expected.push((*handle_a, *handle_b));
+                }
+            }
+        }
+
+        assert!(expected.len() > 10, "too few collisions to compare");
+        assert_eq!(world.collisions(), expected);
+    }
+
+    #[test]
+    fn thinks_run_in_handle_order() {
+        let mut world = WasmWorld::default();
+        let module = world.register_module();
+        let handles = (0..64)
+            .map(|_| world.create_entity(module).unwrap())
+            .collect::<Vec<_>>();
+
+        // Register in a scrambled order
+        for idx in 0..handles.len() {
+            let idx = idx * 37 % handles.len();
+            world.set_think(handles[idx], Some(idx as u32 + 1));
+        }
+
+        let expected = handles
+            .iter()
+            .enumerate()
+            .map(|(idx, &handle)| (handle, idx as u32 + 1))
+            .collect::<Vec<_>>();
+
+        assert_eq!(world.thinks(module), expected);
+    }
+
+    #[test]
+    fn unload_module_leaves_other_modules_alone() {
+        let (mut world, handle) = populated();
+        let module = world.owner(handle).unwrap();
+        let other = ModuleId(1);
+        let kept = world.create_entity(other).unwrap();
+        world.schedule(module, None, 1.0, 6).unwrap();
+        world.schedule(other, None, 1.0, 7).unwrap();
+        world.subscribe(other, "topic");
+
+        world.unload_module(module);
+
+        assert!(world.entity(handle).is_none());
+        assert_eq!(world.think(handle), None);
+        assert!(!world.is_subscribed(module, "topic"));
+        assert!(world.take_messages().is_empty());
+        assert_eq!(world.owner(kept), Some(other));
+        assert!(world.is_subscribed(other, "topic"));
+
+        world.advance_clock(1.0);
+        let timers = world.take_due_timers();
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers[0].callback, 7);
+    }
+
+    /// Two worlds built by the same calls, which have differently seeded
+    /// hash maps
+    fn twins() -> [WasmWorld; 2] {
+        [(); 2].map(|()| {
+            let mut world = WasmWorld::default();
+            let modules = [world.register_module(), world.register_module()];
+
+            for idx in 0..64 {
+                let module = modules[idx % 2];
+                world.create_entity(module).unwrap();
+                world.subscribe(module, &format!("topic {idx}"));
+            }
+
+            world
+        })
+    }
+
+    #[test]
+    fn equal_worlds_snapshot_the_same() {
+        let [a, b] = twins();
+        assert_eq!(snapshot(&a), snapshot(&b));
+    }
+
+    #[test]
+    fn handles_after_unload_are_deterministic() {
+        let [mut a, mut b] = twins();
+        let module = ModuleId(0);
+        a.unload_module(module);
+        b.unload_module(module);
+
+        for _ in 0..64 {
+            assert_eq!(a.create_entity(module), b.create_entity(module));
+        }
+
+        assert_eq!(snapshot(&a), snapshot(&b));
+    }
+
+    #[test]
+    fn restore_round_trips() {
+        let (world, handle) = populated();
+        let module = world.owner(handle).unwrap();
+
+        let mut restored = WasmWorld::default();
+        restored.register_module();
+        restored.register_module();
+        restored.restore(&mut snapshot(&world).as_slice()).unwrap();
+
+        assert_eq!(restored.owner(handle), Some(module));
+        assert_eq!(restored.think(handle), Some(3));
+        assert!(restored.is_subscribed(module, "topic"));
+        assert_eq!(restored.take_messages()[0].data, [1, 2, 3]);
+
+        restored.advance_clock(1.0);
+        let timers = restored.take_due_timers();
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers[0].callback, 4);
+    }
+
+    #[test]
+    fn restore_refuses_other_module_counts() {
+        let (world, handle) = populated();
+        let mut restored = WasmWorld::default();
+        restored.register_module();
+
+        assert!(restored.restore(&mut snapshot(&world).as_slice()).is_err());
+        assert_eq!(restored.owner(handle), None);
+    }
+
+    #[test]
+    fn restore_leaves_world_alone_on_bad_input() {
+        let (world, handle) = populated();
+        let bytes = snapshot(&world);
+
+        for len in 0..bytes.len() {
+            let (mut restored, _) = populated();
+            restored.remove_entity(handle);
+
+            assert!(restored.restore(&mut &bytes[..len]).is_err(), "{len}");
+            assert_eq!(restored.owner(handle), None, "{len}");
+        }
+    }
+
+    #[test]
+    fn restore_rejects_thinks_and_timers_on_dead_entities() {
+        for tie_timer in [false, true] {
+            let mut world = WasmWorld::default();
+            let module = world.register_module();
+            let live = world.create_entity(module).unwrap();
+            let dead = world.create_entity(module).unwrap();
+
+            if tie_timer {
+                world.schedule(module, Some(live), 1.0, 0).unwrap();
+            } else {
+                world.set_think(live, Some(1));
+            }
+
+            world.remove_entity(dead);
+
+            // Swap the live handle for the dead one after the owner table
+            let mut bytes = snapshot(&world);
+            let live_bytes = live.bits().to_le_bytes();
+            let at = bytes
+                .windows(live_bytes.len())
+                .rposition(|window| window == live_bytes)
+                .unwrap();
+            bytes[at..at + live_bytes.len()]
+                .copy_from_slice(&dead.bits().to_le_bytes());
+
+            let error = world.restore(&mut bytes.as_slice()).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn restore_rejects_oversized_lengths() {
+        let mut world = WasmWorld::default();
+        let module = world.register_module();
+        world.subscribe(module, "topic");
+        let mut bytes = snapshot(&world);
+
+        // The topic's length prefix follows the subscriber count
+        let topic = "topic".as_bytes();
+        let at = bytes
+            .windows(topic.len())
+            .position(|window| window == topic)
+            .unwrap()
+            - size_of::<u64>();
+        bytes[at..at + size_of::<u64>()]
+            .copy_from_slice(&u64::MAX.to_le_bytes());
+
+        let error = world.restore(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,61 @@
+//! Runs the base game headlessly.  These need the module built, so they're
+//! ignored by default; run them with
+//! `cargo build --release --target wasm32-unknown-unknown -p base-game`
+//! and then `cargo test -p pipe-cleaner-engine -- --ignored`.
+
+use pipe_cleaner_engine::harness::Harness;
+use pipe_cleaner_engine::wasm::Config;
+use pipe_cleaner_shared::Button;
+use std::path::{Path, PathBuf};
+
+const MODULE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../target/wasm32-unknown-unknown/release/base_game.wasm"
+);
+
+fn harness(seed: u64) -> Harness {
+    assert!(
+        Path::new(MODULE).exists(),
+        "{MODULE} hasn't been built, see the top of this file"
+    );
+
+    let tmp = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+
+    let config = Config {
+        data_dir: Some(tmp.join("data")),
+        cache_dir: Some(tmp.join("cache")),
+        ..Config::default()
+    };
+
+    Harness::new(&[MODULE], seed, config).expect("base game loads")
+}
+
+#[test]
+#[ignore = "needs the base game built for wasm32-unknown-unknown"]
+fn init_spawns_one_entity() {
+    let harness = harness(1);
+
+    assert_eq!(harness.loaded().len(), 1);
+    assert_eq!(harness.entities().len(), 1);
+}
+
+#[test]
+#[ignore = "needs the base game built for wasm32-unknown-unknown"]
+fn same_seed_same_start() {
+    let a = harness(7).entities()[0].engine_fields.position.angle;
+    let b = harness(7).entities()[0].engine_fields.position.angle;
+    assert_eq!(a, b);
+}
+
+#[test]
+#[ignore = "needs the base game built for wasm32-unknown-unknown"]
+fn holding_right_moves_entity() {
+    let mut harness = harness(1);
+
+    let start = harness.entities()[0].engine_fields.position.angle;
+    harness.run(120, &[Button::Right]);
+    let end = harness.entities()[0].engine_fields.position.angle;
+
+    assert_eq!(harness.loaded().len(), 1);
+    assert_ne!(start, end);
+}
@@ -0,0 +1,215 @@
+//! Runs a small module written in the text format, so the harness is tested
+//! without building anything for wasm32-unknown-unknown first.
+
+use pipe_cleaner_engine::error::Error;
+use pipe_cleaner_engine::harness::Harness;
+use pipe_cleaner_engine::wasm::Config;
+use pipe_cleaner_shared::ABI_VERSION;
+use std::fs;
+use std::path::PathBuf;
+
+/// Spawns one entity and turns it by `dt` radians each update.  The angle is
+/// the first field of an entity, so it sits at the address the entity is
+/// copied to.
+fn spinner() -> String {
+    format!(
+        r#"
+        (module
+          (import "env" "PIPECLEANER_create_entity"
+            (func $create_entity (result i64)))
+          (import "env" "PIPECLEANER_get_entity"
+            (func $get_entity (param i64 i32) (result i32)))
+          (import "env" "PIPECLEANER_write_entity_back"
+            (func $write_entity_back (param i64 i32) (result i32)))
+
+          (memory (export "memory") 1)
+          (global $spinner (mut i64) (i64.const 0))
+
+          (func (export "PIPECLEANER_abi_version") (result i32)
+            (i32.const {ABI_VERSION}))
+
+          (func (export "PIPECLEANER_init")
+            (global.set $spinner (call $create_entity)))
+
+          (func (export "PIPECLEANER_update") (param $dt f32)
+            (drop (call $get_entity (global.get $spinner) (i32.const 0)))
+            (f32.store (i32.const 0)
+              (f32.add (f32.load (i32.const 0)) (local.get $dt)))
+            (drop (call $write_entity_back
+              (global.get $spinner) (i32.const 0)))))
+        "#
+    )
+}
+
+/// Harness loading the spinner `copies` times, all from the same file
+fn load(name: &str, copies: usize) -> Result<Harness, Error> {
+    let tmp = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::create_dir_all(&tmp).unwrap();
+
+    let module = tmp.join("spinner.wasm");
+    fs::write(&module, wat::parse_str(spinner()).unwrap()).unwrap();
+
+    let config = Config {
+        data_dir: Some(tmp.join("data")),
+        cache_dir: None,
+        ..Config::default()
+    };
+
+    Harness::new(&vec![module; copies], 1, config)
+}
+
+fn harness(name: &str) -> Harness {
+    load(name, 1).expect("spinner loads")
+}
+
+#[test]
+fn init_spawns_one_entity() {
+    let harness = harness("init");
+
+    assert_eq!(harness.loaded(), ["spinner"]);
+    assert_eq!(harness.entities().len(), 1);
+}
+
+#[test]
+fn update_runs_each_tick() {
+    let mut harness = harness("update").with_tick_rate(10.0);
+
+    harness.run(5, &[]);
+    let angle = harness.entities()[0].engine_fields.position.angle;
+
+    assert!((angle - 0.5).abs() < 1e-4, "{angle}");
+}
+
+#[test]
+fn second_module_with_same_name_is_refused() {
+    match load("duplicate", 2) {
+        Err(Error::DuplicateModule(name)) => assert_eq!(name, "spinner"),
+        Err(e) => panic!("wrong error: {e}"),
+        Ok(_) => panic!("loaded the same module twice"),
+    }
+}
+
+/// The spinner again, as a component against the `game` world.  Entities go
+/// through `get-entity` and `write-entity-back` whole, game fields included,
+/// so this covers lifting and lowering them both ways.
+#[cfg(feature = "component")]
+const SPINNER_COMPONENT: &str = r#"
+    (component
+      (import "pipe-cleaner:engine/host@0.1.0" (instance $host
+        (type $position' (record (field "angle" f32) (field "depth" f32)))
+        (export "pipe-position" (type $position (eq $position')))
+        (type $fields' (record
+          (field "position" $position)
+          (field "velocity" (tuple f32 f32))
+          (field "target-velocity" (tuple f32 f32))
+          (field "max-acceleration" f32)
+          (field "max-speed" f32)
+          (field "color" (tuple f32 f32 f32))
+          (field "model" u32)
+          (field "collision-radius" f32)
+          (field "collision-layers" u32)
+          (field "collision-mask" u32)))
+        (export "engine-fields" (type $fields (eq $fields')))
+        (type $entity' (record
+          (field "engine-fields" $fields)
+          (field "game-fields" (list u32))))
+        (export "entity" (type $entity (eq $entity')))
+        (export "create-entity" (func (result (option u64))))
+        (export "get-entity"
+          (func (param "handle" u64) (result (option $entity))))
+        (export "write-entity-back"
+          (func (param "handle" u64) (param "entity" $entity) (result bool)))))
+
+      (core module $Memory
+        (memory (export "memory") 1)
+        (global $next (mut i32) (i32.const 1024))
+        (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+          (local $ptr i32)
+          (local.set $ptr
+            (i32.and
+              (i32.add (global.get $next) (i32.sub (local.get 2) (i32.const 1)))
+              (i32.sub (i32.const 0) (local.get 2))))
+          (global.set $next (i32.add (local.get $ptr) (local.get 3)))
+          (local.get $ptr)))
+      (core instance $memory (instantiate $Memory))
+      (alias core export $memory "memory" (core memory $mem))
+      (alias core export $memory "realloc" (core func $realloc))
+
+      (alias export $host "create-entity" (func $create-entity))
+      (alias export $host "get-entity" (func $get-entity))
+      (alias export $host "write-entity-back" (func $write-entity-back))
+      (core func $create-entity (canon lower (func $create-entity) (memory $mem)))
+      (core func $get-entity
+        (canon lower (func $get-entity) (memory $mem) (realloc $realloc)))
+      (core func $write-entity-back
+        (canon lower (func $write-entity-back) (memory $mem)))
+
+      (core module $Game
+        (import "memory" "memory" (memory 1))
+        (import "host" "create-entity" (func $create-entity (param i32)))
+        (import "host" "get-entity" (func $get-entity (param i64 i32)))
+        (import "host" "write-entity-back"
+          (func $write-entity-back (param i32) (result i32)))
+        (global $spinner (mut i64) (i64.const 0))
+
+        ;; option<handle> at 0, with the handle at 8
+        (func (export "init")
+          (call $create-entity (i32.const 0))
+          (global.set $spinner (i64.load (i32.const 8))))
+
+        ;; option<entity> at 16, with the entity at 20, and the arguments
+        ;; to write it back at 256, with the entity at 264
+        (func (export "update") (param $dt f32)
+          (call $get-entity (global.get $spinner) (i32.const 16))
+          (i64.store (i32.const 256) (global.get $spinner))
+          (memory.copy (i32.const 264) (i32.const 20) (i32.const 68))
+          (f32.store (i32.const 264)
+            (f32.add (f32.load (i32.const 264)) (local.get $dt)))
+          (drop (call $write-entity-back (i32.const 256))))
+
+        (func (export "on-collision") (param i64 i64))
+        (func (export "on-timer") (param i64 i32)))
+      (core instance $game (instantiate $Game
+        (with "memory" (instance $memory))
+        (with "host" (instance
+          (export "create-entity" (func $create-entity))
+          (export "get-entity" (func $get-entity))
+          (export "write-entity-back" (func $write-entity-back))))))
+
+      (func (export "init") (canon lift (core func $game "init")))
+      (func (export "update") (param "dt" f32)
+        (canon lift (core func $game "update")))
+      (func (export "on-collision") (param "mine" u64) (param "other" u64)
+        (canon lift (core func $game "on-collision")))
+      (func (export "on-timer") (param "handle" u64) (param "callback" u32)
+        (canon lift (core func $game "on-timer"))))
+"#;
+
+#[test]
+#[cfg(feature = "component")]
+fn component_round_trips_entities() {
+    let tmp = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("component");
+    fs::create_dir_all(&tmp).unwrap();
+
+    let module = tmp.join("spinner.wasm");
+    fs::write(&module, wat::parse_str(SPINNER_COMPONENT).unwrap()).unwrap();
+
+    let config = Config {
+        data_dir: None,
+        cache_dir: None,
+        ..Config::default()
+    };
+
+    let mut harness = Harness::new(&[module], 1, config)
+        .expect("spinner component loads")
+        .with_tick_rate(10.0);
+
+    assert_eq!(harness.loaded(), ["spinner"]);
+    harness.run(5, &[]);
+
+    let entities = harness.entities();
+    assert_eq!(entities.len(), 1);
+
+    let angle = entities[0].engine_fields.position.angle;
+    assert!((angle - 0.5).abs() < 1e-4, "{angle}");
+}
@@ -0,0 +1,225 @@
+//! Bindings for the `game` world in `wit/pipe-cleaner.wit`, used instead of
+//! the raw imports when the `component` feature is on.  Build for
+//! `wasm32-unknown-unknown` as usual, then turn the module into a component
+//! with `wasm-tools component new`.
+//!
+//! Component builds get a subset of this crate.  `shims` stands in for the
+//! raw imports, so `EntityRef`, `Query`, `log`, `input`, `random` and timers
+//! call the WIT functions instead.  The world has no thinks, storage,
+//! messages or models, so `EntityRef::set_think`, `Think`, `storage`,
+//! `message` and `model` don't exist in component builds; games that need
+//! them have to use the raw imports.
+//!
+//! Instead of `PIPECLEANER_*` exports, a component implements `Guest`:
+//!
+//! ```ignore
+//! struct MyGame;
+//!
+//! impl pipe_cleaner_game_lib::component::Guest for MyGame {
+//!     fn init() {}
+//!     fn update(dt: f32) {}
+//!     fn on_collision(mine: u64, other: u64) {}
+//!     fn on_timer(handle: u64, callback: u32) {}
+//! }
+//!
+//! pipe_cleaner_game_lib::export_game!(MyGame);
+//! ```
+
+wit_bindgen::generate!({
+    path: "../wit",
+    world: "game",
+    pub_export_macro: true,
+    export_macro_name: "export_game",
+    default_bindings_module: "pipe_cleaner_game_lib::component",
+});
+
+use pipe_cleaner::engine::host;
+use pipe_cleaner::engine::types;
+use pipe_cleaner_shared as shared;
+
+impl From<shared::PipePosition> for types::PipePosition {
+    fn from(position: shared::PipePosition) -> Self {
+        Self {
+            angle: position.angle,
+            depth: position.depth,
+        }
+    }
+}
+
+impl From<types::PipePosition> for shared::PipePosition {
+    fn from(position: types::PipePosition) -> Self {
+        Self {
+            angle: position.angle,
+            depth: position.depth,
+        }
+    }
+}
+
+impl From<shared::Entity> for types::Entity {
+    fn from(entity: shared::Entity) -> Self {
+        let fields = entity.engine_fields;
+        let [vx, vy] = fields.velocity;
+        let [tx, ty] = fields.target_velocity;
+        let [r, g, b] = fields.color;
+
+        Self {
+            engine_fields: types::EngineFields {
+                position: fields.position.into(),
+                velocity: (vx, vy),
+                target_velocity: (tx, ty),
+                max_acceleration: fields.max_acceleration,
+                max_speed: fields.max_speed,
+                color: (r, g, b),
+                model: fields.model,
+                collision_radius: fields.collision_radius,
+                collision_layers: fields.collision_layers,
+                collision_mask: fields.collision_mask,
+            },
+            game_fields: { entity.game_fields }.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<types::Entity> for shared::Entity {
+    type Error = ();
+
+    /// Fails if the game fields are the wrong length
+    fn try_from(entity: types::Entity) -> Result<Self, ()> {
+        let fields = entity.engine_fields;
+        let (vx, vy) = fields.velocity;
+        let (tx, ty) = fields.target_velocity;
+        let (r, g, b) = fields.color;
+
+        Ok(Self {
+            engine_fields: shared::EngineFields {
+                position: fields.position.into(),
+                velocity: [vx, vy],
+                target_velocity: [tx, ty],
+                max_acceleration: fields.max_acceleration,
+                max_speed: fields.max_speed,
+                color: [r, g, b],
+                model: fields.model,
+                collision_radius: fields.collision_radius,
+                collision_layers: fields.collision_layers,
+                collision_mask: fields.collision_mask,
+            },
+            game_fields: entity.game_fields.try_into().map_err(|_| ())?,
+        })
+    }
+}
+
+/// The raw imports, with the same signatures and failure codes, implemented
+/// on top of the WIT functions
+#[allow(non_snake_case, clippy::missing_safety_doc)]
+pub mod shims {
+    use super::{host, types};
+    use core::slice;
+    use pipe_cleaner_shared::{Entity, EntityQuery, InputState};
+
+    pub unsafe fn PIPECLEANER_create_entity() -> u64 {
+        host::create_entity().unwrap_or(0)
+    }
+
+    pub unsafe fn PIPECLEANER_get_entity(handle: u64, ptr: *mut Entity) -> u32 {
+        match host::get_entity(handle).map(Entity::try_from) {
+            Some(Ok(entity)) => {
+                unsafe { ptr.write_unaligned(entity) };
+                0
+            }
+            _ => 1,
+        }
+    }
+
+    pub unsafe fn PIPECLEANER_write_entity_back(
+        handle: u64,
+        ptr: *const Entity,
+    ) -> u32 {
+        let entity = unsafe { ptr.read_unaligned() };
+
+        if host::write_entity_back(handle, &entity.into()) {
+            0
+        } else {
+            1
+        }
+    }
+
+    pub unsafe fn PIPECLEANER_remove_entity(handle: u64) -> u32 {
+        if host::remove_entity(handle) { 0 } else { 1 }
+    }
+
+    pub unsafe fn PIPECLEANER_log(level: u32, ptr: *const u8, len: usize) -> u32 {
+        let level = match level {
+            1 => types::LogLevel::Error,
+            2 => types::LogLevel::Warn,
+            3 => types::LogLevel::Info,
+            4 => types::LogLevel::Debug,
+            5 => types::LogLevel::Trace,
+            _ => return 1,
+        };
+
+        let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+
+        match core::str::from_utf8(bytes) {
+            Ok(message) => {
+                host::log(level, message);
+                0
+            }
+            Err(_) => 1,
+        }
+    }
+
+    pub unsafe fn PIPECLEANER_input_state(ptr: *mut InputState) -> u32 {
+        let input = host::input();
+
+        let state = InputState {
+            held: input.held,
+            pressed: input.pressed,
+        };
+
+        unsafe { ptr.write_unaligned(state) };
+        0
+    }
+
+    pub unsafe fn PIPECLEANER_query_entities(
+        query: *const EntityQuery,
+        out: *mut u64,
+        out_cap: usize,
+        count: *mut u32,
+    ) -> u32 {
+        let query = unsafe { query.read_unaligned() };
+
+        let handles = host::query_entities(types::EntityQuery {
+            filters: query.filters,
+            model: query.model,
+            position: query.position.into(),
+            angle_range: query.angle_range,
+            depth_range: query.depth_range,
+        });
+
+        let copied = handles.len().min(out_cap);
+
+        unsafe {
+            out.copy_from_nonoverlapping(handles.as_ptr(), copied);
+            count.write(handles.len() as u32);
+        }
+
+        0
+    }
+
+    pub unsafe fn PIPECLEANER_random() -> u64 {
+        host::random()
+    }
+
+    pub unsafe fn PIPECLEANER_schedule(
+        handle: u64,
+        delay: f32,
+        callback: u32,
+    ) -> u64 {
+        let handle = (handle != 0).then_some(handle);
+        host::schedule(handle, delay, callback).unwrap_or(0)
+    }
+
+    pub unsafe fn PIPECLEANER_cancel_timer(id: u64) -> u32 {
+        if host::cancel_timer(id) { 0 } else { 1 }
+    }
+}
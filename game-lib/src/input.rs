@@ -0,0 +1,15 @@
+use bytemuck::Zeroable;
+
+use crate::sys::PIPECLEANER_input_state;
+pub use pipe_cleaner_shared::{Button, InputState};
+
+/// Player input for the current tick
+pub fn state() -> InputState {
+    let mut state = InputState::zeroed();
+
+    unsafe {
+        PIPECLEANER_input_state(&mut state);
+    }
+
+    state
+}
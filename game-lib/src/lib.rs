@@ -2,15 +2,33 @@
 #![feature(sync_unsafe_cell)]
 #![feature(ptr_as_ref_unchecked)]
 
+extern crate alloc;
+
 use core::ops::{Deref, DerefMut};
 
+#[cfg(feature = "component")]
+pub mod component;
+pub mod input;
+pub mod log;
+pub mod manifest;
+#[cfg(not(feature = "component"))]
+pub mod message;
+#[cfg(not(feature = "component"))]
+pub mod model;
+pub mod query;
+pub mod random;
+#[cfg(not(feature = "component"))]
+pub mod storage;
 pub mod sys;
+pub mod timer;
 use sys::{
     PIPECLEANER_get_entity,
     PIPECLEANER_create_entity,
     PIPECLEANER_remove_entity,
     PIPECLEANER_write_entity_back,
 };
+#[cfg(not(feature = "component"))]
+use sys::PIPECLEANER_set_think;
 
 use pipe_cleaner_shared as shared;
 pub use shared::{EngineFields, PipePosition};
@@ -26,18 +44,36 @@ pub struct Entity<T: Pod> {
     pub game_fields: T,
 }
 
+/// Behaviour run by the engine every tick for an entity, given its handle
+#[cfg(not(feature = "component"))]
+pub type Think = fn(u64);
+
+// The engine only copies entities to and from 4-byte aligned addresses, and
+// `Entity` itself is only byte aligned
+#[repr(C, align(4))]
 pub struct EntityRef<T: Pod> {
     handle: u64,
     inner: Entity<T>,
 }
 
 impl<T: Pod> EntityRef<T> {
+    /// Create an entity, panicking if the module is at its entity limit
     pub fn spawn() -> Self {
+        Self::try_spawn().expect("Entity limit reached")
+    }
+
+    /// Create an entity, or return `None` if the module is at its entity
+    /// limit
+    pub fn try_spawn() -> Option<Self> {
         let handle = unsafe { PIPECLEANER_create_entity() };
 
-        Self {
-            handle,
-            inner: Zeroable::zeroed()
+        if handle == 0 {
+            None
+        } else {
+            Some(Self {
+                handle,
+                inner: Zeroable::zeroed()
+            })
         }
     }
 
@@ -46,22 +82,34 @@ impl<T: Pod> EntityRef<T> {
     }
 
     pub fn from_handle(handle: u64) -> Option<Self> {
-        let mut entity = Zeroable::zeroed();
-        
+        let mut entity_ref = Self {
+            handle,
+            inner: Zeroable::zeroed(),
+        };
+
         let failure_code = unsafe {
-            PIPECLEANER_get_entity(handle, cast_mut(&mut entity) as _)
+            PIPECLEANER_get_entity(handle, cast_mut(&mut entity_ref.inner) as _)
         };
 
-        if failure_code == 0 {
+        if failure_code != 0 {
+            // Nothing was read, so there's nothing to write back
+            core::mem::forget(entity_ref);
             None
         } else {
-            Some( Self {
-                handle,
-                inner: entity,
-            })
+            Some(entity_ref)
         }
     }
 
+    /// Have `think` called with this entity's handle every tick, or stop if
+    /// `None`.  Thinks are forgotten when the module is hot-reloaded, so set
+    /// them again from `PIPECLEANER_reload`.
+    #[cfg(not(feature = "component"))]
+    pub fn set_think(&self, think: Option<Think>) -> bool {
+        let think = think.map_or(0, |think| think as usize as u32);
+
+        unsafe { PIPECLEANER_set_think(self.handle, think) == 0 }
+    }
+
     pub fn remove(self) {
         unsafe {
             PIPECLEANER_remove_entity(self.handle);
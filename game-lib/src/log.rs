@@ -0,0 +1,95 @@
+use core::fmt::{self, Write};
+
+use crate::sys::PIPECLEANER_log;
+pub use pipe_cleaner_shared::LogLevel;
+
+const LOG_MESSAGE_SZ: usize = 512;
+
+/// Send a message to the host console
+pub fn log(level: LogLevel, message: &str) {
+    unsafe {
+        PIPECLEANER_log(level as u32, message.as_ptr(), message.len());
+    }
+}
+
+/// Format a message and send it to the host console.  Messages longer than
+/// `LOG_MESSAGE_SZ` bytes are cut short.
+pub fn log_fmt(level: LogLevel, args: fmt::Arguments) {
+    let mut writer = LogWriter {
+        buffer: [0; _],
+        length: 0,
+    };
+
+    let _ = writer.write_fmt(args);
+
+    // Only whole characters are ever written to the buffer
+    let message = unsafe {
+        core::str::from_utf8_unchecked(&writer.buffer[..writer.length])
+    };
+
+    log(level, message);
+}
+
+struct LogWriter {
+    buffer: [u8; LOG_MESSAGE_SZ],
+    length: usize,
+}
+
+impl Write for LogWriter {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for ch in string.chars() {
+            let byte_len = ch.len_utf8();
+
+            if self.length + byte_len > LOG_MESSAGE_SZ {
+                break;
+            }
+
+            ch.encode_utf8(&mut self.buffer[self.length..]);
+            self.length += byte_len;
+        }
+
+        Ok(())
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::log_fmt($level, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::LogLevel::Error, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::LogLevel::Warn, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::LogLevel::Info, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::LogLevel::Debug, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::LogLevel::Trace, $($arg)+)
+    };
+}
@@ -0,0 +1,114 @@
+//! Support for the `manifest!` macro, which describes the module to the
+//! engine in a `pipecleaner_manifest` custom section
+
+use pipe_cleaner_shared::ABI_VERSION;
+
+pub use pipe_cleaner_shared::capability;
+
+/// Length of the manifest text for `fields`, plus the ABI version line
+pub const fn encoded_len(fields: &[(&str, &str)]) -> usize {
+    let mut len = "abi=\n".len() + digit_count(ABI_VERSION);
+    let mut idx = 0;
+
+    while idx < fields.len() {
+        len += fields[idx].0.len() + fields[idx].1.len() + "=\n".len();
+        idx += 1;
+    }
+
+    len
+}
+
+/// Manifest text for `fields`, one `key=value` line each, followed by the ABI
+/// version this crate was built against.  `N` must be `encoded_len(fields)`.
+pub const fn encode<const N: usize>(fields: &[(&str, &str)]) -> [u8; N] {
+    let mut out = [0; N];
+    let mut pos = 0;
+    let mut idx = 0;
+
+    while idx < fields.len() {
+        pos = append(&mut out, pos, fields[idx].0.as_bytes());
+        pos = append(&mut out, pos, b"=");
+        pos = append(&mut out, pos, fields[idx].1.as_bytes());
+        pos = append(&mut out, pos, b"\n");
+        idx += 1;
+    }
+
+    pos = append(&mut out, pos, b"abi=");
+
+    let mut divisor = 10u32.pow(digit_count(ABI_VERSION) as u32 - 1);
+
+    while divisor > 0 {
+        out[pos] = b'0' + (ABI_VERSION / divisor % 10) as u8;
+        pos += 1;
+        divisor /= 10;
+    }
+
+    pos = append(&mut out, pos, b"\n");
+    assert!(pos == N, "Manifest length mismatch");
+    out
+}
+
+const fn append<const N: usize>(
+    out: &mut [u8; N],
+    pos: usize,
+    bytes: &[u8],
+) -> usize {
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        out[pos + idx] = bytes[idx];
+        idx += 1;
+    }
+
+    pos + bytes.len()
+}
+
+const fn digit_count(mut value: u32) -> usize {
+    let mut count = 1;
+
+    while value >= 10 {
+        value /= 10;
+        count += 1;
+    }
+
+    count
+}
+
+/// Describe the module to the engine.  Name and version are required; the
+/// name may only contain ASCII letters, digits, `_` and `-`.  Capabilities
+/// are names from `manifest::capability`, and the engine refuses to let the
+/// module do anything needing one it didn't ask for.
+///
+/// ```ignore
+/// manifest! {
+///     name: "base_game",
+///     version: "0.1.0",
+///     author: "Somebody",
+///     capabilities: [capability::STORAGE],
+/// }
+/// ```
+#[macro_export]
+macro_rules! manifest {
+    (
+        name: $name:expr,
+        version: $version:expr
+        $(, author: $author:expr)?
+        $(, capabilities: [$($capability:expr),* $(,)?])?
+        $(,)?
+    ) => {
+        const _: () = {
+            const FIELDS: &[(&str, &str)] = &[
+                ("name", $name),
+                ("version", $version),
+                $(("author", $author),)?
+                $($(("capability", $capability),)*)?
+            ];
+
+            // Must match `pipe_cleaner_shared::MANIFEST_SECTION`
+            #[used]
+            #[unsafe(link_section = "pipecleaner_manifest")]
+            static MANIFEST: [u8; $crate::manifest::encoded_len(FIELDS)] =
+                $crate::manifest::encode(FIELDS);
+        };
+    };
+}
@@ -0,0 +1,63 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::sys::{
+    PIPECLEANER_post,
+    PIPECLEANER_read_message,
+    PIPECLEANER_subscribe,
+    PIPECLEANER_unsubscribe,
+};
+pub use pipe_cleaner_shared::{MAX_MESSAGE_SZ, MAX_TOPIC_SZ};
+
+/// Message posted by another module, as passed to `PIPECLEANER_on_message`
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub topic: String,
+    pub kind: u32,
+    pub data: Vec<u8>,
+}
+
+/// Have the engine call `PIPECLEANER_on_message` for messages other modules
+/// post to `topic`.  Fails if the topic is empty or longer than
+/// `MAX_TOPIC_SZ`.
+pub fn subscribe(topic: &str) -> bool {
+    unsafe { PIPECLEANER_subscribe(topic.as_ptr(), topic.len()) == 0 }
+}
+
+/// Returns false if the module wasn't subscribed to `topic`
+pub fn unsubscribe(topic: &str) -> bool {
+    unsafe { PIPECLEANER_unsubscribe(topic.as_ptr(), topic.len()) == 0 }
+}
+
+/// Post `data` to every module subscribed to `topic`, tagged with `kind`.
+/// They receive it on the next tick.  Fails if `data` is longer than
+/// `MAX_MESSAGE_SZ` or the module has posted too much this tick.
+pub fn post(topic: &str, kind: u32, data: &[u8]) -> bool {
+    unsafe {
+        PIPECLEANER_post(
+            topic.as_ptr(),
+            topic.len(),
+            kind,
+            data.as_ptr(),
+            data.len(),
+        ) == 0
+    }
+}
+
+/// Copy out the message being delivered, given the arguments to
+/// `PIPECLEANER_on_message`.  Only works during that call.
+pub fn receive(kind: u32, topic_len: u32, data_len: u32) -> Option<Message> {
+    let mut topic = vec![0u8; topic_len as usize];
+    let mut data = vec![0u8; data_len as usize];
+
+    let failure_code = unsafe {
+        PIPECLEANER_read_message(topic.as_mut_ptr(), data.as_mut_ptr())
+    };
+
+    if failure_code != 0 {
+        return None;
+    }
+
+    Some(Message { topic: String::from_utf8(topic).ok()?, kind, data })
+}
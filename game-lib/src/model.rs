@@ -0,0 +1,22 @@
+use crate::sys::PIPECLEANER_register_model;
+
+/// Register a line-segment mesh, returning the model id to put in
+/// `EngineFields::model`.  Each pair of `indices` joins two `vertices`.
+///
+/// Only works from `PIPECLEANER_init`; fails if the mesh is malformed or
+/// larger than `MAX_MODEL_VERTICES` or `MAX_MODEL_INDICES`.
+pub fn register(vertices: &[[f32; 3]], indices: &[u32]) -> Option<u32> {
+    let mut model = 0;
+
+    let code = unsafe {
+        PIPECLEANER_register_model(
+            vertices.as_ptr(),
+            vertices.len(),
+            indices.as_ptr(),
+            indices.len(),
+            &mut model,
+        )
+    };
+
+    (code == 0).then_some(model)
+}
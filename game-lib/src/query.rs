@@ -0,0 +1,81 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use bytemuck::Zeroable;
+
+use crate::sys::PIPECLEANER_query_entities;
+pub use pipe_cleaner_shared::{EntityQuery, PipePosition, QueryFilter};
+
+/// Search for live entities, including those created by other modules
+#[derive(Clone, Copy)]
+pub struct Query(EntityQuery);
+
+impl Query {
+    /// Match every entity
+    pub fn all() -> Self {
+        Self(EntityQuery::zeroed())
+    }
+
+    /// Only match entities with the given model
+    pub fn with_model(mut self, model: u32) -> Self {
+        self.0.filters |= QueryFilter::Model as u32;
+        self.0.model = model;
+        self
+    }
+
+    /// Only match entities within `angle_range` radians and `depth_range`
+    /// units of `position`
+    pub fn near(
+        mut self,
+        position: PipePosition,
+        angle_range: f32,
+        depth_range: f32,
+    ) -> Self {
+        self.0.filters |= QueryFilter::Window as u32;
+        self.0.position = position;
+        self.0.angle_range = angle_range;
+        self.0.depth_range = depth_range;
+        self
+    }
+
+    /// Only match entities created by this module
+    pub fn owned(mut self) -> Self {
+        self.0.filters |= QueryFilter::Owned as u32;
+        self
+    }
+
+    /// Fill `out` with handles of matching entities.  Returns the total
+    /// number of matches, which may be more than `out.len()`.
+    pub fn find(&self, out: &mut [u64]) -> usize {
+        let mut count = 0u32;
+
+        let failure_code = unsafe {
+            PIPECLEANER_query_entities(
+                &self.0,
+                out.as_mut_ptr(),
+                out.len(),
+                &mut count,
+            )
+        };
+
+        if failure_code != 0 { 0 } else { count as usize }
+    }
+
+    /// Handle of any one matching entity
+    pub fn first(&self) -> Option<u64> {
+        let mut out = [0u64];
+
+        if self.find(&mut out) > 0 {
+            Some(out[0])
+        } else {
+            None
+        }
+    }
+
+    /// Handles of every matching entity
+    pub fn collect(&self) -> Vec<u64> {
+        let mut out = vec![0u64; self.find(&mut [])];
+        let count = self.find(&mut out);
+        out.truncate(count);
+        out
+    }
+}
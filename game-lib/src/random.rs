@@ -0,0 +1,23 @@
+use crate::sys::PIPECLEANER_random;
+
+/// Next number from the engine's seeded generator.  The sequence is shared by
+/// every loaded module and repeats exactly when the engine is given the same
+/// `--seed`.
+pub fn next_u64() -> u64 {
+    unsafe { PIPECLEANER_random() }
+}
+
+/// Uniform in `0.0..1.0`
+pub fn next_f32() -> f32 {
+    (next_u64() >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Uniform in `low..high`
+pub fn range(low: f32, high: f32) -> f32 {
+    low + (high - low) * next_f32()
+}
+
+/// Uniform in `0..n`, or 0 if `n` is 0
+pub fn below(n: u32) -> u32 {
+    (((next_u64() >> 32) * n as u64) >> 32) as u32
+}
@@ -0,0 +1,54 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::sys::{
+    PIPECLEANER_storage_get,
+    PIPECLEANER_storage_remove,
+    PIPECLEANER_storage_set,
+};
+pub use pipe_cleaner_shared::{MAX_STORAGE_KEY_SZ, MAX_STORAGE_VALUE_SZ};
+
+/// Copy the value saved under `key` into `buf`.  Returns its length, or
+/// `None` if the key isn't set or the value doesn't fit.
+pub fn get_into(key: &str, buf: &mut [u8]) -> Option<usize> {
+    let mut len = 0u32;
+
+    let failure_code = unsafe {
+        PIPECLEANER_storage_get(
+            key.as_ptr(),
+            key.len(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut len,
+        )
+    };
+
+    if failure_code != 0 { None } else { Some(len as usize) }
+}
+
+/// Value saved under `key` by this module, in this or an earlier run
+pub fn get(key: &str) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; MAX_STORAGE_VALUE_SZ];
+    let len = get_into(key, &mut buf)?;
+    buf.truncate(len);
+    Some(buf)
+}
+
+/// Save `value` under `key`.  Fails if the key is empty or longer than
+/// `MAX_STORAGE_KEY_SZ`, the value is longer than `MAX_STORAGE_VALUE_SZ`, or
+/// the module would go over its storage quota.
+pub fn set(key: &str, value: &[u8]) -> bool {
+    unsafe {
+        PIPECLEANER_storage_set(
+            key.as_ptr(),
+            key.len(),
+            value.as_ptr(),
+            value.len(),
+        ) == 0
+    }
+}
+
+/// Returns false if the key wasn't set
+pub fn remove(key: &str) -> bool {
+    unsafe { PIPECLEANER_storage_remove(key.as_ptr(), key.len()) == 0 }
+}
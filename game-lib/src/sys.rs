@@ -5,7 +5,8 @@ use core::panic::PanicInfo;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use pipe_cleaner_shared as shared;
-use shared::{Entity};
+#[cfg(not(feature = "component"))]
+use shared::{Entity, EntityQuery, InputState};
 
 const PANIC_MESSAGE_SZ: usize = 256;
 
@@ -64,7 +65,7 @@ impl<'a> Write for PanicWriter<'a> {
     }
 }
 
-#[unsafe(no_mangle)]
+#[cfg_attr(not(feature = "component"), unsafe(no_mangle))]
 static PIPECLEANER_panic_report: SyncUnsafeCell<PanicReport> =
     SyncUnsafeCell::new(PanicReport::new());
 
@@ -95,13 +96,76 @@ fn panic(info: &PanicInfo) -> ! {
     unreachable();
 }
 
+#[cfg(not(feature = "component"))]
+#[unsafe(no_mangle)]
+pub extern "C" fn PIPECLEANER_abi_version() -> u32 {
+    shared::ABI_VERSION
+}
+
+/// Called by the engine each tick for every entity with a think function
+#[cfg(not(feature = "component"))]
+#[unsafe(no_mangle)]
+pub extern "C" fn PIPECLEANER_dispatch_think(think: u32, handle: u64) {
+    // `think` is the function's index in the module's table, which is what a
+    // function pointer is on wasm32
+    let think: crate::Think = unsafe { core::mem::transmute(think as usize) };
+    think(handle);
+}
+
 #[global_allocator]
 static ALLOCATOR: talc::Talck<spin::Mutex<()>, talc::ErrOnOom> =
     talc::Talc::new(talc::ErrOnOom).lock();
 
+#[cfg(feature = "component")]
+pub use crate::component::shims::*;
+
+#[cfg(not(feature = "component"))]
 unsafe extern "C" {
     pub fn PIPECLEANER_create_entity() -> u64;
     pub fn PIPECLEANER_get_entity(handle: u64, ptr: *mut Entity) -> u32;
     pub fn PIPECLEANER_write_entity_back(handle: u64, ptr: *const Entity) -> u32;
     pub fn PIPECLEANER_remove_entity(handle: u64) -> u32;
+    pub fn PIPECLEANER_log(level: u32, ptr: *const u8, len: usize) -> u32;
+    pub fn PIPECLEANER_input_state(ptr: *mut InputState) -> u32;
+    pub fn PIPECLEANER_query_entities(
+        query: *const EntityQuery,
+        out: *mut u64,
+        out_cap: usize,
+        count: *mut u32,
+    ) -> u32;
+    pub fn PIPECLEANER_set_think(handle: u64, think: u32) -> u32;
+    pub fn PIPECLEANER_random() -> u64;
+    pub fn PIPECLEANER_schedule(handle: u64, delay: f32, callback: u32) -> u64;
+    pub fn PIPECLEANER_cancel_timer(id: u64) -> u32;
+    pub fn PIPECLEANER_storage_get(
+        key: *const u8,
+        key_len: usize,
+        buf: *mut u8,
+        buf_cap: usize,
+        len: *mut u32,
+    ) -> u32;
+    pub fn PIPECLEANER_storage_set(
+        key: *const u8,
+        key_len: usize,
+        value: *const u8,
+        value_len: usize,
+    ) -> u32;
+    pub fn PIPECLEANER_storage_remove(key: *const u8, key_len: usize) -> u32;
+    pub fn PIPECLEANER_subscribe(topic: *const u8, topic_len: usize) -> u32;
+    pub fn PIPECLEANER_unsubscribe(topic: *const u8, topic_len: usize) -> u32;
+    pub fn PIPECLEANER_post(
+        topic: *const u8,
+        topic_len: usize,
+        kind: u32,
+        data: *const u8,
+        data_len: usize,
+    ) -> u32;
+    pub fn PIPECLEANER_read_message(topic: *mut u8, data: *mut u8) -> u32;
+    pub fn PIPECLEANER_register_model(
+        vertices: *const [f32; 3],
+        vertex_ct: usize,
+        indices: *const u32,
+        index_ct: usize,
+        model: *mut u32,
+    ) -> u32;
 }
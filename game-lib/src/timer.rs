@@ -0,0 +1,22 @@
+use crate::sys::{PIPECLEANER_cancel_timer, PIPECLEANER_schedule};
+
+/// Pending call to the module's `PIPECLEANER_on_timer` export
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timer(u64);
+
+impl Timer {
+    /// Have the engine call `PIPECLEANER_on_timer(handle, callback)` after
+    /// `delay` seconds of game time.  If `handle` isn't 0 the timer is
+    /// dropped when that entity is removed.
+    pub fn schedule(handle: u64, delay: f32, callback: u32) -> Option<Self> {
+        match unsafe { PIPECLEANER_schedule(handle, delay, callback) } {
+            0 => None,
+            id => Some(Self(id)),
+        }
+    }
+
+    /// Returns false if the timer already fired or was cancelled
+    pub fn cancel(self) -> bool {
+        unsafe { PIPECLEANER_cancel_timer(self.0) == 0 }
+    }
+}
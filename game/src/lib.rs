@@ -4,8 +4,26 @@ use pipe_cleaner_game_lib::{
     EntityRef,
     GAME_FIELDS_SZ,
     PipePosition,
+    info,
+    input::{self, Button},
+    manifest,
+    manifest::capability,
+    message,
+    model,
+    query::Query,
+    random,
+    storage,
+    timer::Timer,
 };
 use bytemuck::{Zeroable, Pod};
+use core::f32::consts::TAU;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+manifest! {
+    name: "base_game",
+    version: env!("CARGO_PKG_VERSION"),
+    capabilities: [capability::STORAGE],
+}
 
 #[repr(C, packed(4))]
 #[derive(Clone, Copy, Zeroable, Pod)]
@@ -16,8 +34,34 @@ struct MyFields {
     _pad: [u32; GAME_FIELDS_SZ - 4],
 }
 
+const DIAMOND_VERTICES: [[f32; 3]; 6] = [
+    [0.06, 0.0, 0.0],
+    [-0.06, 0.0, 0.0],
+    [0.0, 0.06, 0.0],
+    [0.0, -0.06, 0.0],
+    [0.0, 0.0, 0.06],
+    [0.0, 0.0, -0.06],
+];
+
+const DIAMOND_INDICES: [u32; 24] = [
+    0, 2, 0, 3, 0, 4, 0, 5,
+    1, 2, 1, 3, 1, 4, 1, 5,
+    2, 4, 4, 3, 3, 5, 5, 2,
+];
+
+const HEARTBEAT: u32 = 1;
+const HEARTBEAT_INTERVAL: f32 = 5.0;
+
+/// Topic other modules can subscribe to for this module's collisions
+const COLLISIONS_TOPIC: &str = "base_game.collisions";
+const COLLISION_MESSAGE: u32 = 1;
+
+static ME: AtomicU64 = AtomicU64::new(0);
+
 #[unsafe(no_mangle)]
 pub extern "C" fn PIPECLEANER_init() {
+    let diamond = model::register(&DIAMOND_VERTICES, &DIAMOND_INDICES)
+        .expect("diamond model rejected");
     let mut entity_ref = EntityRef::<MyFields>::spawn();
     let handle = entity_ref.handle();
     let entity = &mut *entity_ref;
@@ -25,7 +69,109 @@ pub extern "C" fn PIPECLEANER_init() {
     entity.game_fields.bar = 12.7;
     entity.game_fields.me = handle;
     entity.engine_fields.position = PipePosition {
-        angle: 13.7,
+        angle: random::range(0.0, TAU),
         depth: 3.2,
     };
+    entity.engine_fields.color = [1.0, 0.0, 1.0];
+    entity.engine_fields.model = diamond;
+    entity.engine_fields.max_acceleration = 40.0;
+    entity.engine_fields.max_speed = 4.0;
+    entity.engine_fields.collision_radius = 0.05;
+    entity.engine_fields.collision_layers = 1;
+    entity.engine_fields.collision_mask = 1;
+    entity_ref.set_think(Some(pulse));
+    Timer::schedule(handle, HEARTBEAT_INTERVAL, HEARTBEAT);
+    ME.store(handle, Ordering::Relaxed);
+    info!("Spawned entity {handle:#x}");
+
+    let launches = storage::get("launches")
+        .and_then(|bytes| bytes.try_into().ok())
+        .map_or(0, u32::from_le_bytes)
+        + 1;
+
+    storage::set("launches", &launches.to_le_bytes());
+    info!("Launched {launches} times");
+
+    message::subscribe(COLLISIONS_TOPIC);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn PIPECLEANER_update(_dt: f32) {
+    if let Some(mut entity_ref) =
+        EntityRef::<MyFields>::from_handle(ME.load(Ordering::Relaxed))
+    {
+        let input = input::state();
+        let mut direction = 0.0;
+
+        if input.is_held(Button::Left) {
+            direction -= 1.0;
+        }
+
+        if input.is_held(Button::Right) {
+            direction += 1.0;
+        }
+
+        let max_speed = entity_ref.engine_fields.max_speed;
+        entity_ref.engine_fields.target_velocity = [direction * max_speed, 0.0];
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn PIPECLEANER_reload() {
+    if let Some(handle) = Query::all().owned().first() {
+        ME.store(handle, Ordering::Relaxed);
+
+        if let Some(entity_ref) = EntityRef::<MyFields>::from_handle(handle) {
+            entity_ref.set_think(Some(pulse));
+        }
+
+        info!("Reloaded, found entity {handle:#x}");
+    }
+}
+
+fn pulse(handle: u64) {
+    if let Some(mut entity_ref) = EntityRef::<MyFields>::from_handle(handle) {
+        let ticks = entity_ref.game_fields.foo.wrapping_add(1);
+        let brightness = 0.5 + 0.5 * (ticks % 60) as f32 / 60.0;
+        entity_ref.game_fields.foo = ticks;
+        entity_ref.engine_fields.color = [brightness, 0.0, brightness];
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn PIPECLEANER_on_collision(mine: u64, other: u64) {
+    info!("Entity {mine:#x} collided with {other:#x}");
+
+    let mut data = [0u8; 16];
+    data[..8].copy_from_slice(&mine.to_le_bytes());
+    data[8..].copy_from_slice(&other.to_le_bytes());
+    message::post(COLLISIONS_TOPIC, COLLISION_MESSAGE, &data);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn PIPECLEANER_on_message(
+    kind: u32,
+    topic_len: u32,
+    data_len: u32,
+) {
+    if let Some(message) = message::receive(kind, topic_len, data_len) {
+        info!(
+            "Message of kind {} on {} with {} bytes",
+            message.kind,
+            message.topic,
+            message.data.len()
+        );
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn PIPECLEANER_on_timer(handle: u64, callback: u32) {
+    if callback == HEARTBEAT
+        && let Some(entity_ref) = EntityRef::<MyFields>::from_handle(handle)
+    {
+        let position = entity_ref.engine_fields.position;
+        let (angle, depth) = (position.angle, position.depth);
+        info!("Entity {handle:#x} at angle {angle}, depth {depth}");
+        Timer::schedule(handle, HEARTBEAT_INTERVAL, HEARTBEAT);
+    }
 }
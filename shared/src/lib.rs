@@ -2,6 +2,37 @@
 
 use bytemuck::{Pod, Zeroable};
 
+/// Version of the interface between the engine and game modules.  Bump this
+/// whenever the layout of a shared type or the signature of a host import
+/// changes.
+pub const ABI_VERSION: u32 = 2;
+
+/// Name of the custom section holding a module's manifest
+pub const MANIFEST_SECTION: &str = "pipecleaner_manifest";
+
+/// Names of the capabilities a module can ask for in its manifest
+pub mod capability {
+    /// Save values with the storage imports
+    pub const STORAGE: &str = "storage";
+    /// Overwrite and remove entities created by other modules
+    pub const FOREIGN_ENTITIES: &str = "foreign_entities";
+}
+
+/// Most vertices a guest may submit for one model
+pub const MAX_MODEL_VERTICES: usize = 4096;
+/// Most indices a guest may submit for one model
+pub const MAX_MODEL_INDICES: usize = 8192;
+
+/// Longest key, in bytes, a guest may save a value under
+pub const MAX_STORAGE_KEY_SZ: usize = 64;
+/// Largest value a guest may save under one key
+pub const MAX_STORAGE_VALUE_SZ: usize = 4096;
+
+/// Longest topic name, in bytes, a guest may post or subscribe to
+pub const MAX_TOPIC_SZ: usize = 64;
+/// Largest message a guest may post
+pub const MAX_MESSAGE_SZ: usize = 4096;
+
 pub const FIELD_SZ: usize = size_of::<u32>();
 pub const ENTITY_SZ: usize = 31;
 
@@ -17,6 +48,13 @@ pub struct EngineFields {
     pub max_speed: f32,
     pub color: [f32; 3],
     pub model: u32,
+    /// Radius of the entity's collision circle on the pipe wall
+    pub collision_radius: f32,
+    /// Bitmask of collision layers the entity is on
+    pub collision_layers: u32,
+    /// Bitmask of collision layers the entity collides with.  Two entities
+    /// collide if either one's mask shares a layer with the other.
+    pub collision_mask: u32,
 }
 
 #[repr(C, packed(4))]
@@ -33,3 +71,91 @@ pub struct Entity {
     pub game_fields:
         [u32; (size_of::<RawFields>() - size_of::<EngineFields>()) / FIELD_SZ],
 }
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryFilter {
+    /// Only entities whose model is `EntityQuery::model`
+    Model = 1 << 0,
+    /// Only entities within `angle_range` radians and `depth_range` units of
+    /// `EntityQuery::position`
+    Window = 1 << 1,
+    /// Only entities created by the calling module
+    Owned = 1 << 2,
+}
+
+/// Criteria for finding entities.  An entity matches if it passes every
+/// filter enabled in `filters`.
+#[repr(C, packed(4))]
+#[derive(Clone, Copy, Zeroable, Pod)]
+pub struct EntityQuery {
+    /// Bitmask of `QueryFilter`s
+    pub filters: u32,
+    pub model: u32,
+    pub position: PipePosition,
+    pub angle_range: f32,
+    pub depth_range: f32,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Left = 1 << 0,
+    Right = 1 << 1,
+    Fire = 1 << 2,
+}
+
+/// Player input for the current tick
+#[repr(C, packed(4))]
+#[derive(Clone, Copy, Default, Zeroable, Pod)]
+pub struct InputState {
+    /// Bitmask of buttons held down
+    pub held: u32,
+    /// Bitmask of buttons pressed since the previous tick
+    pub pressed: u32,
+}
+
+impl InputState {
+    pub fn is_held(&self, button: Button) -> bool {
+        self.held & button as u32 != 0
+    }
+
+    pub fn was_pressed(&self, button: Button) -> bool {
+        self.pressed & button as u32 != 0
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl LogLevel {
+    pub fn from_u32(level: u32) -> Option<Self> {
+        match level {
+            1 => Some(Self::Error),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Info),
+            4 => Some(Self::Debug),
+            5 => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl core::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        })
+    }
+}